@@ -0,0 +1,439 @@
+//! Generates the Rust twins of the HLSL structs in `shaders/shared.hlsli`,
+//! included by `src/shared.rs`.
+//!
+//! Members are laid out like DXC lays out push constants and buffers by
+//! default, std430 with relaxed vector alignment. Padding becomes explicit
+//! fields so the types can be `Pod`, and every offset and size is asserted at
+//! compile time against what `#[repr(C)]` makes of the Rust fields. That is
+//! what proves the types have no implicit padding.
+//!
+//! Whether the offsets are the ones DXC uses is checked by compiling a push
+//! constant block of every struct and comparing the member offsets DXC
+//! decorates it with, which fails the build on a mismatch. That needs DXC when
+//! building. Without it the build only warns, and the guarantee is left to
+//! tests/reflection.rs, which compares the `MEMBERS` constant of every struct
+//! with reflection at runtime.
+//!
+//! Structs that need no padding, sit every member at its strict std430 offset
+//! and have no 64-bit members also derive `encase::ShaderType`, so they can be
+//! written with `typed_buffer::Layout`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::Path,
+};
+
+const HEADER: &str = "shaders/shared.hlsli";
+
+fn main() {
+    println!("cargo:rerun-if-changed={HEADER}");
+
+    let source = fs::read_to_string(HEADER).unwrap_or_else(|error| panic!("Could not read {HEADER}: {error}"));
+    let (code, layouts) = generate(&source).unwrap_or_else(|(line, message)| panic!("{HEADER}:{line}: {message}"));
+
+    let out = Path::new(&env::var_os("OUT_DIR").expect("Cargo sets OUT_DIR")).join("shared.rs");
+    fs::write(&out, code).unwrap_or_else(|error| panic!("Could not write {out:?}: {error}"));
+
+    check_with_dxc(&source, &layouts);
+}
+
+/// Members of a generated struct, by HLSL name and byte offset.
+struct Layout {
+    name: String,
+    members: Vec<(String, u32)>,
+}
+
+/// Fails the build when DXC puts a member of a struct somewhere else than the
+/// generated Rust struct does.
+fn check_with_dxc(source: &str, layouts: &[Layout]) {
+    if hassle_rs::Dxc::new(None).is_err() {
+        println!(
+            "cargo:warning=DXC is not available, the layouts of {HEADER} are only checked at runtime by tests/reflection.rs"
+        );
+        return;
+    }
+
+    for Layout { name, members } in layouts {
+        let shader = format!(
+            "{source}\n\
+             [[vk::push_constant]] {name} pushConstants;\n\
+             [numthreads(1, 1, 1)] void main() {{ vk::RawBufferStore<{name}>(0, pushConstants); }}\n"
+        );
+        let spirv = hassle_rs::compile_hlsl(HEADER, &shader, "main", "cs_6_6", &["-spirv"], &[])
+            .unwrap_or_else(|error| panic!("DXC could not compile {name} of {HEADER}: {error}"));
+        let reflected = push_constant_members(&spirv);
+        if reflected != *members {
+            panic!("{HEADER}: DXC lays out {name} as {reflected:?}, the generated struct as {members:?}");
+        }
+    }
+}
+
+/// Names and offsets of the members of the push constant block in `spirv`.
+fn push_constant_members(spirv: &[u8]) -> Vec<(String, u32)> {
+    const MEMBER_NAME: u32 = 6;
+    const TYPE_POINTER: u32 = 32;
+    const VARIABLE: u32 = 59;
+    const MEMBER_DECORATE: u32 = 72;
+    const OFFSET: u32 = 35;
+    const PUSH_CONSTANT: u32 = 9;
+
+    let words: Vec<u32> = spirv
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let mut instructions = Vec::new();
+    let mut offset = 5;
+    while offset < words.len() {
+        let word_count = ((words[offset] >> 16) as usize).max(1);
+        instructions.push(&words[offset..(offset + word_count).min(words.len())]);
+        offset += word_count;
+    }
+
+    let mut pointers = HashMap::new();
+    let mut block = None;
+    for instruction in &instructions {
+        match (instruction[0] & 0xffff, &instruction[1..]) {
+            (TYPE_POINTER, [pointer, PUSH_CONSTANT, pointee]) => {
+                pointers.insert(*pointer, *pointee);
+            }
+            (VARIABLE, [pointer, _, PUSH_CONSTANT, ..]) => block = pointers.get(pointer).copied(),
+            _ => {}
+        }
+    }
+    let block = block.expect("The shader declares a push constant block");
+
+    let mut members: BTreeMap<u32, (String, u32)> = BTreeMap::new();
+    for instruction in &instructions {
+        match (instruction[0] & 0xffff, &instruction[1..]) {
+            (MEMBER_NAME, [id, member, name @ ..]) if *id == block => {
+                let bytes: Vec<u8> = name.iter().flat_map(|word| word.to_le_bytes()).collect();
+                let name = bytes.split(|byte| *byte == 0).next().unwrap_or_default();
+                members.entry(*member).or_default().0 = String::from_utf8_lossy(name).into_owned();
+            }
+            (MEMBER_DECORATE, [id, member, OFFSET, offset]) if *id == block => {
+                members.entry(*member).or_default().1 = *offset;
+            }
+            _ => {}
+        }
+    }
+    members.into_values().collect()
+}
+
+/// Error with the line it was found on.
+type Error = (usize, String);
+
+/// Layout of an HLSL type and the Rust type with the same bytes.
+#[derive(Clone)]
+struct Type {
+    rust: String,
+    size: u32,
+    /// Base alignment of std430.
+    align: u32,
+    /// Component size for vectors, which may sit at any multiple of it as long
+    /// as they do not straddle a 16 byte boundary.
+    relaxed_align: Option<u32>,
+    /// Whether encase lays it out the same and can derive `ShaderType` for it.
+    shader_type: bool,
+}
+
+impl Type {
+    fn scalar(rust: &str, size: u32) -> Type {
+        Type {
+            rust: rust.to_owned(),
+            size,
+            align: size,
+            relaxed_align: None,
+            shader_type: size == 4,
+        }
+    }
+
+    fn vector(rust: &str, components: u32) -> Type {
+        Type {
+            rust: rust.to_owned(),
+            size: 4 * components,
+            align: if components == 2 { 8 } else { 16 },
+            relaxed_align: Some(4),
+            shader_type: true,
+        }
+    }
+
+    /// Offset of the first byte at or after `offset` the type can start at.
+    fn place(&self, offset: u32) -> u32 {
+        match self.relaxed_align {
+            Some(align) => {
+                let offset = align_up(offset, align);
+                if self.size <= 16 && offset / 16 != (offset + self.size - 1) / 16 {
+                    align_up(offset, 16)
+                } else {
+                    offset
+                }
+            }
+            None => align_up(offset, self.align),
+        }
+    }
+}
+
+fn align_up(offset: u32, align: u32) -> u32 {
+    offset.div_ceil(align) * align
+}
+
+fn builtin(name: &str) -> Option<Type> {
+    Some(match name {
+        "float" => Type::scalar("f32", 4),
+        "int" => Type::scalar("i32", 4),
+        "uint" | "dword" => Type::scalar("u32", 4),
+        "int64_t" => Type::scalar("i64", 8),
+        "uint64_t" => Type::scalar("u64", 8),
+        "double" => Type::scalar("f64", 8),
+        "float4x4" => Type {
+            rust: "glam::Mat4".to_owned(),
+            size: 64,
+            align: 16,
+            relaxed_align: None,
+            shader_type: true,
+        },
+        _ => {
+            let (rust, components) = [("float", "glam::Vec"), ("int", "glam::IVec"), ("uint", "glam::UVec")]
+                .iter()
+                .find_map(|(scalar, rust)| {
+                    let components: u32 = name.strip_prefix(scalar)?.parse().ok()?;
+                    (2..=4).contains(&components).then_some((*rust, components))
+                })?;
+            Type::vector(&format!("{rust}{components}"), components)
+        }
+    })
+}
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+/// Words and punctuation of `source`, without comments and preprocessor lines.
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut in_block_comment = false;
+    for (index, line) in source.lines().enumerate() {
+        let mut rest = line;
+        if !in_block_comment && rest.trim_start().starts_with('#') {
+            continue;
+        }
+        while !rest.is_empty() {
+            if in_block_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        rest = &rest[end + 2..];
+                        in_block_comment = false;
+                    }
+                    None => break,
+                }
+                continue;
+            }
+            rest = rest.trim_start();
+            if rest.starts_with("//") {
+                break;
+            }
+            if let Some(after) = rest.strip_prefix("/*") {
+                rest = after;
+                in_block_comment = true;
+                continue;
+            }
+            let Some(first) = rest.chars().next() else {
+                break;
+            };
+            let length = if first.is_alphanumeric() || first == '_' {
+                rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len())
+            } else {
+                first.len_utf8()
+            };
+            tokens.push(Token {
+                text: &rest[..length],
+                line: index + 1,
+            });
+            rest = &rest[length..];
+        }
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn is_done(&self) -> bool {
+        self.position == self.tokens.len()
+    }
+
+    fn next(&mut self) -> Result<&Token<'a>, Error> {
+        let last_line = self.tokens.last().map_or(1, |token| token.line);
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| (last_line, "Unexpected end of file".to_owned()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Error> {
+        let token = self.next()?;
+        if token.text != expected {
+            return Err((token.line, format!("Expected `{expected}`, found `{}`", token.text)));
+        }
+        Ok(())
+    }
+}
+
+/// Rust name of an HLSL member, `vertexId` becomes `vertex_id`.
+fn rust_field(member: &str) -> String {
+    let mut field = String::new();
+    for (index, c) in member.chars().enumerate() {
+        if c.is_uppercase() && index > 0 && !field.ends_with('_') {
+            field.push('_');
+        }
+        field.extend(c.to_lowercase());
+    }
+    const KEYWORDS: &[&str] = &["type", "match", "ref", "move", "fn", "impl", "mod", "use", "loop", "where"];
+    if KEYWORDS.contains(&field.as_str()) {
+        field.insert_str(0, "r#");
+    }
+    field
+}
+
+fn generate(source: &str) -> Result<(String, Vec<Layout>), Error> {
+    let mut parser = Parser {
+        tokens: tokenize(source),
+        position: 0,
+    };
+    let mut structs: HashMap<String, Type> = HashMap::new();
+    let mut code = format!("// Generated by build.rs from {HEADER}, edit that file instead.\n");
+    let mut layouts = Vec::new();
+
+    while !parser.is_done() {
+        parser.expect("struct")?;
+        let token = parser.next()?;
+        let (name, line) = (token.text.to_owned(), token.line);
+        if structs.contains_key(&name) || builtin(&name).is_some() {
+            return Err((line, format!("Struct {name} is declared twice or shadows a builtin type")));
+        }
+        parser.expect("{")?;
+
+        let mut fields = String::new();
+        let mut asserts = String::new();
+        let mut members = Vec::new();
+        let mut layout = Vec::new();
+        let mut offset = 0;
+        let mut align = 4;
+        let mut padding = 0;
+        let mut shader_type = true;
+        let mut add_padding = |fields: &mut String, bytes: u32| {
+            if bytes > 0 {
+                *fields += &format!("    pub _padding{padding}: [u8; {bytes}],\n");
+                padding += 1;
+            }
+        };
+
+        loop {
+            let mut token = parser.next()?;
+            if token.text == "}" {
+                break;
+            }
+            if token.text == "column_major" {
+                token = parser.next()?;
+            }
+            if token.text == "row_major" {
+                return Err((token.line, "row_major matrices have no glam type".to_owned()));
+            }
+            let (type_name, member_line) = (token.text, token.line);
+            let mut member_type = structs
+                .get(type_name)
+                .cloned()
+                .or_else(|| builtin(type_name))
+                .ok_or_else(|| (member_line, format!("Unsupported member type {type_name}")))?;
+            let member = parser.next()?.text;
+
+            let mut token = parser.next()?;
+            if token.text == "[" {
+                let count: u32 = parser
+                    .next()?
+                    .text
+                    .parse()
+                    .map_err(|_| (member_line, format!("Array {member} needs a literal length")))?;
+                parser.expect("]")?;
+                let stride = align_up(member_type.size, member_type.align);
+                if stride != member_type.size {
+                    return Err((
+                        member_line,
+                        format!("Elements of {member} are padded to {stride} bytes, which Rust arrays cannot express"),
+                    ));
+                }
+                member_type = Type {
+                    rust: format!("[{}; {count}]", member_type.rust),
+                    size: stride * count,
+                    align: member_type.align,
+                    relaxed_align: None,
+                    shader_type: member_type.shader_type,
+                };
+                token = parser.next()?;
+            }
+            if token.text != ";" {
+                return Err((
+                    token.line,
+                    format!("Expected `;` after {member}, semantics and initializers are not supported"),
+                ));
+            }
+
+            let member_offset = member_type.place(offset);
+            shader_type &= member_type.shader_type && member_offset == align_up(offset, member_type.align);
+            add_padding(&mut fields, member_offset - offset);
+            let field = rust_field(member);
+            fields += &format!("    /// `{type_name} {member}` at byte {member_offset}.\n");
+            fields += &format!("    pub {field}: {},\n", member_type.rust);
+            asserts += &format!("    assert!(std::mem::offset_of!({name}, {field}) == {member_offset});\n");
+            members.push(format!("(\"{member}\", {member_offset})"));
+            layout.push((member.to_owned(), member_offset));
+            offset = member_offset + member_type.size;
+            align = align.max(member_type.align);
+        }
+        parser.expect(";")?;
+
+        let size = align_up(offset, align);
+        add_padding(&mut fields, size - offset);
+        shader_type &= padding == 0;
+        let derives = if shader_type { ", encase::ShaderType" } else { "" };
+        code += &format!(
+            "\n/// `struct {name}` of `{HEADER}`, {size} bytes.\n\
+             #[repr(C)]\n\
+             #[derive(Clone, Copy, Debug, PartialEq{derives})]\n\
+             pub struct {name} {{\n{fields}}}\n\n\
+             // The asserts check that the fields cover every byte.\n\
+             unsafe impl bytemuck::Zeroable for {name} {{}}\n\
+             unsafe impl bytemuck::Pod for {name} {{}}\n\n\
+             impl {name} {{\n\
+             \x20   /// HLSL name and byte offset of every member, which tests compare with\n\
+             \x20   /// the offsets DXC reflects.\n\
+             \x20   pub const MEMBERS: &[(&str, u32)] = &[{}];\n\
+             }}\n\n\
+             const _: () = {{\n{asserts}    assert!(std::mem::size_of::<{name}>() == {size});\n}};\n",
+            members.join(", ")
+        );
+        layouts.push(Layout {
+            name: name.clone(),
+            members: layout,
+        });
+        structs.insert(
+            name.clone(),
+            Type {
+                rust: name,
+                size,
+                align,
+                relaxed_align: None,
+                shader_type,
+            },
+        );
+    }
+
+    Ok((code, layouts))
+}
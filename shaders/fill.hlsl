@@ -0,0 +1,21 @@
+// Writes each invocation's index times SCALE into a buffer, plus one with
+// OFFSET. Exercises compute dispatches and permutations in tests/compute.rs.
+
+#include "shared.hlsli"
+
+[[vk::push_constant]] FillPushConstants pushConstants;
+
+[[vk::constant_id(0)]] const uint SCALE = 2;
+
+[numthreads(64, 1, 1)]
+void computeMain(uint3 id: SV_DispatchThreadID)
+{
+    if (id.x < pushConstants.count)
+    {
+        uint value = id.x * SCALE;
+#ifdef OFFSET
+        value += 1;
+#endif
+        vk::RawBufferStore<uint>(pushConstants.output_address + id.x * 4, value);
+    }
+}
@@ -0,0 +1,25 @@
+// Structs shared with Rust. build.rs generates a #[repr(C)] twin of each one
+// into vulkan_modern::shared, with the layout DXC gives it in push constants
+// and buffers, and the build fails when the two cannot agree or, if DXC is
+// installed, when DXC puts a member elsewhere. Members can be 32 or 64-bit
+// scalars, vectors of 32-bit scalars, float4x4, arrays and structs declared
+// earlier.
+
+#pragma once
+
+struct Globals
+{
+    float4x4 transform;
+};
+
+struct TrianglePushConstants
+{
+    uint64_t globals_address;
+};
+
+struct FillPushConstants
+{
+    uint64_t output_address;
+    uint count;
+    uint padding;
+};
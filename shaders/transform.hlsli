@@ -0,0 +1,17 @@
+// Vertex math of the triangle, shared by triangle.hlsl and triangle_mesh.hlsl
+// and tested on the GPU in tests/shader_math.rs.
+
+#pragma once
+
+static const float2 TRIANGLE_POSITIONS[3] = {
+    float2(0.0, -0.5),
+    float2(0.5, 0.5),
+    float2(-0.5, 0.5)
+};
+
+// `model` comes from a glam::Mat4. With the default column_major packing its
+// columns are glam's columns, so it multiplies column vectors like in Rust.
+float4 trianglePosition(uint vertexId, float4x4 model)
+{
+    return mul(model, float4(TRIANGLE_POSITIONS[vertexId], 0.0, 1.0));
+}
@@ -1,13 +1,7 @@
-struct PushConstants
-{
-      uint64_t globals_address;
-};
-
-[[vk::push_constant]] PushConstants pushConstants;
+#include "shared.hlsli"
+#include "transform.hlsli"
 
-// struct Globals {
-//     float4x4 model;
-// };
+[[vk::push_constant]] TrianglePushConstants pushConstants;
 
 struct VertexInput
 {
@@ -21,17 +15,10 @@ struct VertexOutput
 
 VertexOutput vertexMain(VertexInput input)
 {
-    float4x4 model = vk::RawBufferLoad<float4x4>(pushConstants.globals_address);
+    Globals globals = vk::RawBufferLoad<Globals>(pushConstants.globals_address);
 
     VertexOutput vertexOutput;
-    
-    float2 positions[3] = {
-        float2(0.0, -0.5),
-        float2(0.5, 0.5),
-        float2(-0.5, 0.5)
-    };
-
-    vertexOutput.position = mul(float4(positions[input.vertexId], 0.0, 1.0), model);
+    vertexOutput.position = trianglePosition(input.vertexId, globals.transform);
 
     return vertexOutput;
 }
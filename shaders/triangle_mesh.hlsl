@@ -0,0 +1,27 @@
+// The triangle of triangle.hlsl emitted by a single mesh shader workgroup.
+// Shares its pixel shader, devices without mesh shaders draw triangle.hlsl.
+
+#include "shared.hlsli"
+#include "transform.hlsli"
+
+[[vk::push_constant]] TrianglePushConstants pushConstants;
+
+struct VertexOutput
+{
+    float4 position: SV_POSITION;
+};
+
+[outputtopology("triangle")]
+[numthreads(1, 1, 1)]
+void meshMain(out indices uint3 triangles[1], out vertices VertexOutput vertices[3])
+{
+    Globals globals = vk::RawBufferLoad<Globals>(pushConstants.globals_address);
+
+    SetMeshOutputCounts(3, 1);
+
+    for (uint i = 0; i < 3; i++)
+    {
+        vertices[i].position = trianglePosition(i, globals.transform);
+    }
+    triangles[0] = uint3(0, 1, 2);
+}
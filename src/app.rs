@@ -1,27 +1,190 @@
-use std::ffi::{c_char, CStr};
+use std::{
+    ffi::{c_char, CStr},
+    path::Path,
+};
 
 use ash::{
     extensions::ext::DebugUtils,
     prelude::VkResult,
-    vk::{self, DebugUtilsMessengerEXT},
+    vk,
     Device, Entry,
 };
 use raw_window_handle::HasRawDisplayHandle;
 use winit::window::Window;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    debug::{DebugConfig, DebugCounters, DebugMessenger, DebugNames},
+    error::Error,
+    hot_reload::ShaderWatcher,
+    shaders::SHADER_DIR,
+    renderer::{DeviceContext, DeviceExtensions, RenderTarget},
+    requirements_filters::{
+        select_physical_device, DeviceOverride, DeviceReport, DeviceRequirements, Feature,
+    },
+};
+
+/// Environment variable holding a [`ValidationConfig::parse`] list.
+pub const VALIDATION_ENV: &str = "VULKAN_VALIDATION";
+
+const VALIDATION_LAYER: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0") };
+
+/// How `VK_LAYER_KHRONOS_validation` is set up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationConfig {
+    /// Load the layer when it is installed.
+    pub enabled: bool,
+    /// Fail instead of continuing without validation when the layer is missing.
+    pub required: bool,
+    /// Synchronization validation, reports missing barriers and hazards.
+    pub synchronization: bool,
+    /// GPU-assisted validation, checks descriptor indexing and buffer device
+    /// addresses while shaders run. Slow.
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            enabled: true,
+            required: false,
+            synchronization: false,
+            gpu_assisted: false,
+            best_practices: false,
+        }
+    }
+}
+
+impl ValidationConfig {
+    /// Parses a comma separated list of `off`, `required`, `sync`, `gpu` and
+    /// `best-practices`. Every item but `off` also enables the layer, so the
+    /// last of `off` and the others wins. `gpu` and `sync` exclude each other,
+    /// the layer does not support both at once.
+    pub fn parse(value: &str) -> Result<ValidationConfig> {
+        let mut config = ValidationConfig::default();
+        for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item {
+                "off" => config.enabled = false,
+                "required" => config.required = true,
+                "sync" => config.synchronization = true,
+                "gpu" => config.gpu_assisted = true,
+                "best-practices" => config.best_practices = true,
+                item => bail!("Unknown validation option {:?}, expected off, required, sync, gpu or best-practices", item),
+            }
+            if item != "off" {
+                config.enabled = true;
+            }
+        }
+        if config.gpu_assisted && config.synchronization {
+            bail!("Validation options gpu and sync cannot be combined");
+        }
+        Ok(config)
+    }
+
+    pub fn from_env() -> Result<Option<ValidationConfig>> {
+        std::env::var(VALIDATION_ENV)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(|value| ValidationConfig::parse(&value))
+            .transpose()
+    }
+
+    fn any_feature(&self) -> bool {
+        self.synchronization || self.gpu_assisted || self.best_practices
+    }
+
+    fn enabled_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+        if self.synchronization {
+            features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.best_practices {
+            features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        features
+    }
+}
+
+/// Startup options of an [`App`].
+#[derive(Clone, Debug, Default)]
+pub struct AppConfig {
+    /// Forces a physical device instead of the best scoring one.
+    pub device: Option<DeviceOverride>,
+    pub validation: ValidationConfig,
+    pub debug: DebugConfig,
+    /// Recompile and swap shaders when their sources change.
+    pub hot_reload: bool,
+}
+
+impl AppConfig {
+    /// Reads the environment first, then lets command line flags override it.
+    ///
+    /// Recognized flags: `--device <index|name>`, `--validation <options>`,
+    /// `--debug-file <path>` and `--hot-reload`.
+    pub fn from_env_and_args(args: impl IntoIterator<Item = String>) -> Result<AppConfig> {
+        let mut config = AppConfig {
+            device: DeviceOverride::from_env(),
+            validation: ValidationConfig::from_env()?.unwrap_or_default(),
+            debug: DebugConfig::default(),
+            hot_reload: false,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if let Some(value) = arg.strip_prefix("--device=") {
+                config.device = Some(DeviceOverride::parse(value));
+            } else if arg == "--device" {
+                let value = args.next().with_context(|| "--device expects an index or a name")?;
+                config.device = Some(DeviceOverride::parse(&value));
+            } else if let Some(value) = arg.strip_prefix("--validation=") {
+                config.validation = ValidationConfig::parse(value)?;
+            } else if arg == "--validation" {
+                let value = args.next().with_context(|| "--validation expects a list of options")?;
+                config.validation = ValidationConfig::parse(&value)?;
+            } else if arg == "--hot-reload" {
+                config.hot_reload = true;
+            } else if let Some(value) = arg.strip_prefix("--debug-file=") {
+                config.debug.file = Some(value.into());
+            } else if arg == "--debug-file" {
+                let value = args.next().with_context(|| "--debug-file expects a path")?;
+                config.debug.file = Some(value.into());
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+const FRAMES_IN_FLIGHT: usize = 2;
+
+/// What [`App::render`] had to recreate before it could continue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// The logical device and all resources created on it were replaced.
+    DeviceLost,
+    /// The window surface and its swapchain were replaced.
+    SurfaceLost,
+}
 
-use crate::{debug::vulkan_debug_callback, requirements_filters::is_device_suitable};
 pub struct App {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
     pub device: ash::Device,
+    pub device_report: DeviceReport,
+    /// Validation as it was set up, `None` when the layer is not loaded.
+    pub validation: Option<ValidationConfig>,
 
-    pub debug_utils_loader: DebugUtils,
-    pub debug_call_back: DebugUtilsMessengerEXT,
+    pub debug_messenger: DebugMessenger,
 
-    pub surface: crate::surface::Surface,
-    pub swapchain: crate::swapchain::Swapchain,
+    pub surface: Option<crate::surface::Surface>,
+    pub target: RenderTarget,
 
     pub queue: ash::vk::Queue,
 
@@ -32,27 +195,56 @@ pub struct App {
 }
 
 impl App {
-    pub fn new(window: &Window, window_width: u32, window_height: u32) -> Result<Self> {
+    pub fn new(
+        window: &Window,
+        window_width: u32,
+        window_height: u32,
+        config: &AppConfig,
+    ) -> Result<Self> {
+        Self::create(Some(window), window_width, window_height, config)
+    }
+
+    /// Creates an app without a window. Frames are drawn into an offscreen color
+    /// image instead of a swapchain, so no display or surface support is needed.
+    pub fn new_headless(width: u32, height: u32, config: &AppConfig) -> Result<Self> {
+        Self::create(None, width, height, config)
+    }
+
+    fn create(
+        window: Option<&Window>,
+        window_width: u32,
+        window_height: u32,
+        config: &AppConfig,
+    ) -> Result<Self> {
         let entry = Entry::linked();
 
         let app_name = unsafe { CStr::from_bytes_with_nul_unchecked(b"VulkanTriangle\0") };
 
-        let layer_names = unsafe {
-            [CStr::from_bytes_with_nul_unchecked(
-                b"VK_LAYER_KHRONOS_validation\0",
-            )]
+        let validation = Self::validation_layer(&entry, &config.validation)?;
+        let layers_names_raw: Vec<*const c_char> = match &validation {
+            Some(_) => vec![VALIDATION_LAYER.as_ptr()],
+            None => Vec::new(),
         };
-        let layers_names_raw: Vec<*const c_char> = layer_names
-            .iter()
-            .map(|raw_name| raw_name.as_ptr())
-            .collect();
 
-        let mut extension_names =
-            ash_window::enumerate_required_extensions(window.raw_display_handle())
+        let mut extension_names = match window {
+            Some(window) => ash_window::enumerate_required_extensions(window.raw_display_handle())
                 .unwrap()
-                .to_vec();
+                .to_vec(),
+            None => Vec::new(),
+        };
         extension_names.push(DebugUtils::name().as_ptr());
 
+        let validation_features = validation
+            .as_ref()
+            .map(ValidationConfig::enabled_features)
+            .unwrap_or_default();
+        if !validation_features.is_empty() {
+            extension_names.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+        }
+        let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&validation_features)
+            .build();
+
         let appinfo = vk::ApplicationInfo::builder()
             .application_name(app_name)
             .application_version(0)
@@ -61,65 +253,149 @@ impl App {
             .api_version(vk::make_api_version(0, 1, 3, 0))
             .build();
 
-        let create_info = vk::InstanceCreateInfo::builder()
+        let mut create_info = vk::InstanceCreateInfo::builder()
             .application_info(&appinfo)
             .enabled_layer_names(&layers_names_raw)
             .enabled_extension_names(&extension_names)
-            .flags(vk::InstanceCreateFlags::default())
-            .build();
+            .flags(vk::InstanceCreateFlags::default());
+        if !validation_features.is_empty() {
+            create_info = create_info.push_next(&mut validation_features_info);
+        }
+        let create_info = create_info.build();
 
         let instance = unsafe { entry.create_instance(&create_info, None)? };
 
-        let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-            .message_severity(
-                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-            )
-            .message_type(
-                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-            )
-            .pfn_user_callback(Some(vulkan_debug_callback))
-            .build();
+        let debug_messenger = DebugMessenger::new(&entry, &instance, config.debug.clone())?;
 
-        let debug_utils_loader = DebugUtils::new(&entry, &instance);
-        let debug_call_back = unsafe {
-            debug_utils_loader
-                .create_debug_utils_messenger(&debug_info, None)
-                .unwrap()
+        let surface = window
+            .map(|window| crate::surface::Surface::new(&entry, &instance, window))
+            .transpose()?;
+
+        let requirements = DeviceRequirements::new(surface.is_some());
+        let device_report = unsafe {
+            select_physical_device(
+                &instance,
+                surface.as_ref().map(|surface| (&surface.loader, &surface.inner)),
+                &requirements,
+                config.device.as_ref(),
+            )?
         };
+        let physical_device = device_report.physical_device;
+        let queue_family_index = device_report
+            .queue_family_index
+            .with_context(|| "Selected device has no graphics queue family")?;
+
+        let (device, extensions) =
+            Self::create_device(&instance, &device_report, queue_family_index)?;
+        let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+
+        let mut renderer = crate::renderer::Renderer::new(
+            DeviceContext {
+                instance: instance.clone(),
+                physical_device,
+                device: device.clone(),
+                extensions,
+                queue,
+                queue_family_index,
+            },
+            DebugNames::new(Some(debug_messenger.loader.clone()), device.handle()),
+            FRAMES_IN_FLIGHT,
+        )?;
+        if config.hot_reload {
+            let watcher = ShaderWatcher::new(Path::new(SHADER_DIR), renderer.shaders().dependencies())?;
+            renderer.set_shader_watcher(Some(watcher));
+        }
 
-        let physical_devices: Vec<vk::PhysicalDevice> =
-            unsafe { instance.enumerate_physical_devices()? };
+        let target = Self::create_target(
+            &instance,
+            &device,
+            &physical_device,
+            &mut renderer,
+            surface.as_ref(),
+            window_width,
+            window_height,
+        )?;
 
-        let surface = crate::surface::Surface::new(&entry, &instance, &window);
+        Ok(App {
+            entry,
+            instance,
+            device,
+            device_report,
+            validation,
+
+            debug_messenger,
+
+            surface,
+            target,
+            queue,
+
+            renderer,
 
-        let (physical_device, queue_family_index) = unsafe {
-            physical_devices
+            width: window_width,
+            height: window_height,
+        })
+    }
+
+    /// Decides whether the validation layer can be loaded, and with which
+    /// features. A missing layer or a layer without `VK_EXT_validation_features`
+    /// only disables what it cannot provide, unless validation is required.
+    fn validation_layer(
+        entry: &ash::Entry,
+        config: &ValidationConfig,
+    ) -> Result<Option<ValidationConfig>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let layer_available = entry
+            .enumerate_instance_layer_properties()?
+            .iter()
+            .any(|layer| {
+                let name = unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) };
+                name == VALIDATION_LAYER
+            });
+        if !layer_available {
+            if config.required {
+                bail!("Validation is required but {:?} is not installed", VALIDATION_LAYER);
+            }
+            log::warn!("{:?} is not installed, running without validation", VALIDATION_LAYER);
+            return Ok(None);
+        }
+
+        let mut config = config.clone();
+        if config.any_feature() {
+            let features_available = entry
+                .enumerate_instance_extension_properties(Some(VALIDATION_LAYER))?
                 .iter()
-                .find_map(|physical_device| {
-                    if let Some(index) = is_device_suitable(
-                        &instance,
-                        &surface.loader,
-                        &surface.inner,
-                        *physical_device,
-                    ) {
-                        return Some((physical_device, index as u32));
-                    } else {
-                        return None;
-                    }
-                })
-                .with_context(|| "")?
-        };
+                .any(|extension| {
+                    let name = unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+                    name == vk::ExtValidationFeaturesFn::name()
+                });
+            if !features_available {
+                if config.required {
+                    bail!("Validation features are required but the layer does not expose them");
+                }
+                log::warn!("Validation layer does not support VK_EXT_validation_features, using default checks");
+                config.synchronization = false;
+                config.gpu_assisted = false;
+                config.best_practices = false;
+            }
+        }
+
+        log::info!("Validation enabled: {:?}", config);
+        Ok(Some(config))
+    }
 
-        let device_extension_names_raw = [
-            ash::extensions::khr::Swapchain::name().as_ptr(),
-            ash::extensions::khr::DynamicRendering::name().as_ptr(),
-            ash::extensions::ext::ShaderObject::name().as_ptr(),
-            ash::extensions::khr::BufferDeviceAddress::name().as_ptr(),
-        ];
+    fn create_device(
+        instance: &ash::Instance,
+        device_report: &DeviceReport,
+        queue_family_index: u32,
+    ) -> crate::error::Result<(Device, DeviceExtensions)> {
+        let device_extension_names_raw: Vec<*const c_char> = device_report
+            .enabled_extensions
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect();
         let features = vk::PhysicalDeviceFeatures::default();
         let priorities = [1.0];
 
@@ -140,92 +416,252 @@ impl App {
             .buffer_device_address(true)
             .build();
 
-        let device_create_info = vk::DeviceCreateInfo::builder()
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::builder()
+            .mesh_shader(true)
+            .task_shader(true)
+            .build();
+
+        // Only chain the feature structs of features the device supports.
+        let mut device_create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(std::slice::from_ref(&queue_info))
             .enabled_extension_names(&device_extension_names_raw)
-            .enabled_features(&features)
-            .push_next(&mut shader_object_features)
-            .push_next(&mut dynamic_rendering_features)
-            .push_next(&mut buffer_device_address)
-            .build();
+            .enabled_features(&features);
+        if device_report.has_feature(Feature::ShaderObject) {
+            device_create_info = device_create_info.push_next(&mut shader_object_features);
+        }
+        if device_report.has_feature(Feature::DynamicRendering) {
+            device_create_info = device_create_info.push_next(&mut dynamic_rendering_features);
+        }
+        if device_report.has_feature(Feature::BufferDeviceAddress) {
+            device_create_info = device_create_info.push_next(&mut buffer_device_address);
+        }
+        if device_report.has_feature(Feature::MeshShader) {
+            device_create_info = device_create_info.push_next(&mut mesh_shader_features);
+        }
+        let device_create_info = device_create_info.build();
 
-        let device: Device =
-            unsafe { instance.create_device(*physical_device, &device_create_info, None)? };
+        let device: Device = unsafe {
+            instance.create_device(device_report.physical_device, &device_create_info, None)?
+        };
         let _dynamic_rendering_loader =
-            ash::extensions::khr::DynamicRendering::new(&instance, &device);
-        let shader_object_loader = ash::extensions::ext::ShaderObject::new(&instance, &device);
+            ash::extensions::khr::DynamicRendering::new(instance, &device);
+        // Without VK_EXT_shader_object the renderer falls back to pipelines,
+        // without VK_EXT_mesh_shader to vertex shaders.
+        let extensions = DeviceExtensions {
+            shader_object: device_report
+                .has_feature(Feature::ShaderObject)
+                .then(|| ash::extensions::ext::ShaderObject::new(instance, &device)),
+            mesh_shader: device_report
+                .has_feature(Feature::MeshShader)
+                .then(|| ash::extensions::ext::MeshShader::new(instance, &device)),
+        };
 
-        let swapchain = crate::swapchain::Swapchain::new(
-            &instance,
-            &physical_device,
-            &device,
-            &surface,
-            window_width,
-            window_height,
-        );
+        Ok((device, extensions))
+    }
+
+    fn create_target(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_device: &vk::PhysicalDevice,
+        renderer: &mut crate::renderer::Renderer,
+        surface: Option<&crate::surface::Surface>,
+        width: u32,
+        height: u32,
+    ) -> crate::error::Result<RenderTarget> {
+        Ok(match surface {
+            Some(surface) => RenderTarget::Swapchain(Box::new(crate::swapchain::Swapchain::new(
+                instance,
+                physical_device,
+                device,
+                surface,
+                width,
+                height,
+            )?)),
+            None => RenderTarget::Offscreen(renderer.create_offscreen(width, height)?),
+        })
+    }
+
+    /// See [`crate::renderer::Renderer::render`].
+    ///
+    /// A lost device or surface is recovered from instead of being returned as
+    /// an error. The frame is dropped and the returned [`Recovery`] says what
+    /// was recreated. After a device loss persistent buffers, and with them
+    /// [`crate::typed_buffer::TypedBuffer`]s, hold what the host last wrote
+    /// to them again but have new device addresses. Other resources created on
+    /// the old device are gone and have to be created again.
+    pub fn render(&mut self) -> crate::error::Result<Option<Recovery>> {
+        // Left behind by a recovery that failed after destroying the target.
+        if let RenderTarget::Lost = self.target {
+            self.target = self.create_own_target()?;
+        }
+        match self.renderer.render(&mut self.target) {
+            Ok(()) => Ok(None),
+            Err(Error::DeviceLost) => {
+                self.recover_device()?;
+                Ok(Some(Recovery::DeviceLost))
+            }
+            Err(Error::SurfaceLost) => {
+                self.recover_surface()?;
+                Ok(Some(Recovery::SurfaceLost))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Replaces the device and everything created on it. The new device is
+    /// created on the same physical device with the same extensions and
+    /// features, and the renderer keeps its clock and persistent buffers.
+    pub fn recover_device(&mut self) -> crate::error::Result<()> {
+        let queue_family_index = self
+            .device_report
+            .queue_family_index
+            .ok_or(Error::DeviceLost)?;
+        let (device, extensions) =
+            Self::create_device(&self.instance, &self.device_report, queue_family_index)?;
         let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
 
-        let renderer = crate::renderer::Renderer::new(
-            instance.clone(),
-            physical_device.clone(),
-            device.clone(),
-            shader_object_loader,
-            queue,
-            queue_family_index,
-            2,
-        );
+        let mut renderer = match crate::renderer::Renderer::new(
+            DeviceContext {
+                instance: self.instance.clone(),
+                physical_device: self.device_report.physical_device,
+                device: device.clone(),
+                extensions,
+                queue,
+                queue_family_index,
+            },
+            DebugNames::new(Some(self.debug_messenger.loader.clone()), device.handle()),
+            FRAMES_IN_FLIGHT,
+        ) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                unsafe { device.destroy_device(None) };
+                return Err(error);
+            }
+        };
+        if let Err(error) = renderer.restore_persistent_buffers(&mut self.renderer) {
+            renderer.destroy();
+            unsafe { device.destroy_device(None) };
+            return Err(error);
+        }
+        renderer.set_clock(self.renderer.clock());
+        renderer.set_shader_watcher(self.renderer.take_shader_watcher());
 
-        Ok(App {
-            entry,
-            instance,
-            device,
+        let mut old_renderer = std::mem::replace(&mut self.renderer, renderer);
+        let old_device = std::mem::replace(&mut self.device, device);
+        self.queue = queue;
 
-            debug_utils_loader,
-            debug_call_back,
+        unsafe {
+            match std::mem::replace(&mut self.target, RenderTarget::Lost) {
+                RenderTarget::Swapchain(mut swapchain) => swapchain.destroy(),
+                RenderTarget::Offscreen(mut offscreen) => old_renderer.destroy_offscreen(&mut offscreen),
+                RenderTarget::Lost => {}
+            }
+            old_renderer.destroy();
+            old_device.destroy_device(None);
+        }
 
-            surface,
-            swapchain,
-            queue,
+        self.target = self.create_own_target()?;
+        Ok(())
+    }
 
-            renderer,
+    /// Creates the window surface and the swapchain again. Headless apps have
+    /// no surface and are left untouched.
+    pub fn recover_surface(&mut self) -> crate::error::Result<()> {
+        let Some(surface) = &mut self.surface else {
+            return Ok(());
+        };
 
-            width: window_width,
-            height: window_height,
-        })
+        unsafe {
+            let _ = self.device.device_wait_idle();
+        }
+        if let RenderTarget::Swapchain(swapchain) = &mut self.target {
+            swapchain.destroy();
+            self.target = RenderTarget::Lost;
+        }
+        surface.recreate(&self.entry, &self.instance)?;
+
+        self.target = self.create_own_target()?;
+        Ok(())
     }
 
-    pub fn render(&mut self) -> bool {
-        return self.renderer.render(&mut self.swapchain);
+    /// The target for the app's surface, or an offscreen one without it.
+    fn create_own_target(&mut self) -> crate::error::Result<RenderTarget> {
+        Self::create_target(
+            &self.instance,
+            &self.device,
+            &self.device_report.physical_device,
+            &mut self.renderer,
+            self.surface.as_ref(),
+            self.width,
+            self.height,
+        )
     }
 
-    pub fn wait_gpu_idle(&self) {
-        unsafe { self.device.device_wait_idle().unwrap() };
+    /// Reads back the last rendered frame as RGBA8 pixels. Only headless apps
+    /// render into an image that can be read, windowed apps return `None`.
+    pub fn read_pixels(&mut self) -> crate::error::Result<Option<Vec<u8>>> {
+        match &self.target {
+            RenderTarget::Offscreen(offscreen) => self.renderer.read_offscreen(offscreen).map(Some),
+            RenderTarget::Swapchain(_) | RenderTarget::Lost => Ok(None),
+        }
     }
 
-    pub fn recreate_swapchain(&mut self, width: u32, height: u32) {
-        self.wait_gpu_idle();
+    /// Validation and driver messages received so far, per severity.
+    pub fn debug_counters(&self) -> DebugCounters {
+        self.debug_messenger.counters()
+    }
+
+    /// Fails with every validation warning and error reported since the last
+    /// check, listing their message IDs and objects. Needs
+    /// [`DebugConfig::collect`], otherwise nothing is collected and this
+    /// always succeeds.
+    pub fn check_validation(&self) -> crate::error::Result<()> {
+        self.debug_messenger.sink().check()
+    }
+
+    pub fn wait_gpu_idle(&self) -> crate::error::Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        Ok(())
+    }
+
+    pub fn recreate_swapchain(&mut self, width: u32, height: u32) -> crate::error::Result<()> {
+        self.wait_gpu_idle()?;
 
         self.width = width;
         self.height = height;
 
-        self.swapchain.recreate(width, height);
+        match &mut self.target {
+            RenderTarget::Swapchain(swapchain) => swapchain.recreate(width, height),
+            RenderTarget::Offscreen(offscreen) => {
+                let resized = self.renderer.create_offscreen(width, height)?;
+                self.renderer.destroy_offscreen(offscreen);
+                *offscreen = resized;
+                Ok(())
+            }
+            // Created at the new size by the next render.
+            RenderTarget::Lost => Ok(()),
+        }
     }
 }
 
 impl Drop for App {
     fn drop(&mut self) {
         unsafe {
-            self.device.device_wait_idle().unwrap();
-
-            self.swapchain.destroy();
+            // Nothing is left to wait for on a lost device.
+            let _ = self.device.device_wait_idle();
+
+            match &mut self.target {
+                RenderTarget::Swapchain(swapchain) => swapchain.destroy(),
+                RenderTarget::Offscreen(offscreen) => self.renderer.destroy_offscreen(offscreen),
+                RenderTarget::Lost => {}
+            }
             self.renderer.destroy();
 
             self.device.destroy_device(None);
-            self.surface
-                .loader
-                .destroy_surface(self.surface.inner, None);
-            self.debug_utils_loader
-                .destroy_debug_utils_messenger(self.debug_call_back, None);
+            if let Some(surface) = &self.surface {
+                surface.loader.destroy_surface(surface.inner, None);
+            }
+            self.debug_messenger.destroy();
             self.instance.destroy_instance(None);
         }
     }
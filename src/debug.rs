@@ -1,12 +1,270 @@
-use std::{borrow::{Cow, Borrow}, ffi::CStr, fs::{File, OpenOptions}, io::Write};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{CStr, CString},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
 
-use ash::vk;
+use ash::{extensions::ext::DebugUtils, vk};
 
-pub unsafe extern "system" fn vulkan_debug_callback(
+use crate::error::{Error, Result};
+
+/// Target of every record the messenger forwards to `log`.
+pub const LOG_TARGET: &str = "vulkan";
+
+/// Which messages the debug messenger asks for and where they go.
+#[derive(Clone, Debug)]
+pub struct DebugConfig {
+    pub severities: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Every message is also appended to this file. The file is created if needed.
+    pub file: Option<PathBuf>,
+    /// Only log the first message of each message ID. Repeats are still counted.
+    pub deduplicate: bool,
+    /// Keep every warning and error so they can be checked with
+    /// [`DebugSink::check`], see [`crate::app::App::check_validation`].
+    pub collect: bool,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        DebugConfig {
+            severities: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            file: None,
+            deduplicate: true,
+            collect: false,
+        }
+    }
+}
+
+/// One message as received by the callback.
+#[derive(Clone, Debug)]
+pub struct DebugMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub id_name: String,
+    pub id_number: i32,
+    pub message: String,
+    /// Objects the message is about, as `<type> <handle> "<name>"`.
+    pub objects: Vec<String>,
+}
+
+impl std::fmt::Display for DebugMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} [{} ({})]",
+            self.severity, self.message_type, self.id_name, self.id_number
+        )?;
+        if !self.objects.is_empty() {
+            write!(f, " on {}", self.objects.join(", "))?;
+        }
+        write!(f, " : {}", self.message)
+    }
+}
+
+/// Number of messages received per severity, repeats included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugCounters {
+    pub errors: u64,
+    pub warnings: u64,
+    pub infos: u64,
+    pub verbose: u64,
+}
+
+/// Receives the messages of a debug messenger: counts them, drops repeats and
+/// forwards the rest to `log` and the optional file.
+pub struct DebugSink {
+    config: DebugConfig,
+    file: Option<Mutex<File>>,
+    seen: Mutex<HashMap<(i32, String), u64>>,
+    collected: Mutex<Vec<DebugMessage>>,
+
+    errors: AtomicU64,
+    warnings: AtomicU64,
+    infos: AtomicU64,
+    verbose: AtomicU64,
+}
+
+impl DebugSink {
+    pub fn new(config: DebugConfig) -> Result<DebugSink> {
+        let file = config
+            .file
+            .as_ref()
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map(Mutex::new)
+                    .map_err(|source| Error::Io {
+                        path: path.clone(),
+                        source,
+                    })
+            })
+            .transpose()?;
+
+        Ok(DebugSink {
+            config,
+            file,
+            seen: Mutex::new(HashMap::new()),
+            collected: Mutex::new(Vec::new()),
+
+            errors: AtomicU64::new(0),
+            warnings: AtomicU64::new(0),
+            infos: AtomicU64::new(0),
+            verbose: AtomicU64::new(0),
+        })
+    }
+
+    pub fn counters(&self) -> DebugCounters {
+        DebugCounters {
+            errors: self.errors.load(Ordering::Relaxed),
+            warnings: self.warnings.load(Ordering::Relaxed),
+            infos: self.infos.load(Ordering::Relaxed),
+            verbose: self.verbose.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Handles one message. Returns whether it was logged, `false` means it was
+    /// a repeat that deduplication dropped.
+    pub fn handle(&self, message: DebugMessage) -> bool {
+        let (counter, level) = match message.severity {
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => (&self.errors, log::Level::Error),
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => (&self.warnings, log::Level::Warn),
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO => (&self.infos, log::Level::Info),
+            _ => (&self.verbose, log::Level::Trace),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        // Repeats are collected too, a test should see every failing call.
+        if self.config.collect && level <= log::Level::Warn {
+            self.collected.lock().unwrap().push(message.clone());
+        }
+
+        // Messages without an ID, e.g. from the loader, are never deduplicated.
+        let has_id = message.id_number != 0 || !message.id_name.is_empty();
+        if self.config.deduplicate && has_id {
+            let mut seen = self.seen.lock().unwrap();
+            let count = seen
+                .entry((message.id_number, message.id_name.clone()))
+                .or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                return false;
+            }
+        }
+
+        log::log!(target: LOG_TARGET, level, "{message}");
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap();
+            // The log record above already reported the message, a failing
+            // file sink must not take the application down.
+            let _ = writeln!(file, "{message}\n");
+        }
+
+        true
+    }
+
+    /// Removes and returns the collected warnings and errors.
+    pub fn take_messages(&self) -> Vec<DebugMessage> {
+        std::mem::take(&mut *self.collected.lock().unwrap())
+    }
+
+    /// Fails with every warning and error collected since the last check.
+    pub fn check(&self) -> Result<()> {
+        let messages = self.take_messages();
+        if messages.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(messages))
+        }
+    }
+
+    /// Message IDs that were dropped as repeats and how often.
+    pub fn suppressed(&self) -> Vec<(String, i32, u64)> {
+        self.seen
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|((number, name), count)| (name.clone(), *number, count - 1))
+            .collect()
+    }
+}
+
+/// A `VK_EXT_debug_utils` messenger that reports into a [`DebugSink`].
+pub struct DebugMessenger {
+    pub loader: DebugUtils,
+    pub inner: vk::DebugUtilsMessengerEXT,
+
+    // Boxed so the pointer handed to the callback stays valid.
+    sink: Box<DebugSink>,
+}
+
+impl DebugMessenger {
+    pub fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        config: DebugConfig,
+    ) -> Result<DebugMessenger> {
+        let severities = config.severities;
+        let types = config.types;
+        let sink = Box::new(DebugSink::new(config)?);
+
+        let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(severities)
+            .message_type(types)
+            .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(&*sink as *const DebugSink as *mut std::os::raw::c_void)
+            .build();
+
+        let loader = DebugUtils::new(entry, instance);
+        let inner = unsafe { loader.create_debug_utils_messenger(&debug_info, None)? };
+
+        Ok(DebugMessenger {
+            loader,
+            inner,
+            sink,
+        })
+    }
+
+    pub fn counters(&self) -> DebugCounters {
+        self.sink.counters()
+    }
+
+    pub fn sink(&self) -> &DebugSink {
+        &self.sink
+    }
+
+    pub fn destroy(&mut self) {
+        for (name, number, count) in self.sink.suppressed() {
+            log::debug!(target: LOG_TARGET, "Suppressed {count} repeats of [{name} ({number})]");
+        }
+
+        unsafe {
+            self.loader.destroy_debug_utils_messenger(self.inner, None);
+        }
+        self.inner = vk::DebugUtilsMessengerEXT::null();
+    }
+}
+
+unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
     let message_id_number = callback_data.message_id_number;
@@ -23,17 +281,101 @@ pub unsafe extern "system" fn vulkan_debug_callback(
         CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("debug.txt")
-        .unwrap();
-    // let mut file = File::create("debug.txt").expect("");
-    write!(file, "{message_severity:?}:\n{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n",).expect("");
+    let objects = if callback_data.p_objects.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(callback_data.p_objects, callback_data.object_count as usize)
+    };
+    let objects = objects
+        .iter()
+        .map(|object| {
+            let name = if object.p_object_name.is_null() {
+                Cow::from("")
+            } else {
+                CStr::from_ptr(object.p_object_name).to_string_lossy()
+            };
+            format!("{:?} {:#x} {:?}", object.object_type, object.object_handle, name)
+        })
+        .collect();
 
-    println!(
-        "{message_severity:?}:\n{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n",
-    );
+    if let Some(sink) = (user_data as *const DebugSink).as_ref() {
+        sink.handle(DebugMessage {
+            severity: message_severity,
+            message_type,
+            id_name: message_id_name.into_owned(),
+            id_number: message_id_number,
+            message: message.into_owned(),
+            objects,
+        });
+    }
 
     vk::FALSE
-}
\ No newline at end of file
+}
+
+/// Names objects and labels command buffer regions through
+/// `VK_EXT_debug_utils`, so validation messages and captures show names
+/// instead of raw handles. Does nothing when the extension is not loaded.
+#[derive(Clone)]
+pub struct DebugNames {
+    loader: Option<DebugUtils>,
+    device: vk::Device,
+}
+
+impl DebugNames {
+    pub fn new(loader: Option<DebugUtils>, device: vk::Device) -> DebugNames {
+        DebugNames { loader, device }
+    }
+
+    pub fn name<H: vk::Handle>(&self, handle: H, name: &str) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name);
+        // Names are only a debugging aid, failing to set one is not an error.
+        let _ = unsafe { loader.set_debug_utils_object_name(self.device, &name_info) };
+    }
+
+    pub fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+        let name = CString::new(name).unwrap_or_default();
+
+        let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+        unsafe { loader.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+
+    pub fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(loader) = &self.loader {
+            unsafe { loader.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+
+    /// Opens a label region that is closed when the returned guard is dropped.
+    pub fn label(&self, command_buffer: vk::CommandBuffer, name: &str) -> LabelScope<'_> {
+        self.begin_label(command_buffer, name);
+        LabelScope {
+            names: self,
+            command_buffer,
+        }
+    }
+}
+
+/// Label region opened by [`DebugNames::label`].
+pub struct LabelScope<'a> {
+    names: &'a DebugNames,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl Drop for LabelScope<'_> {
+    fn drop(&mut self) {
+        self.names.end_label(self.command_buffer);
+    }
+}
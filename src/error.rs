@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use ash::vk;
+
+/// Errors of the renderer and the objects it is built from.
+///
+/// Out-of-date swapchains, lost surfaces and lost devices get their own
+/// variants so an embedding application can recover from them.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The swapchain no longer matches the surface and has to be recreated.
+    #[error("Swapchain is out of date")]
+    SwapchainOutOfDate,
+    #[error("Surface was lost")]
+    SurfaceLost,
+    #[error("Device was lost")]
+    DeviceLost,
+    #[error("Out of host or device memory")]
+    OutOfMemory,
+    #[error("Could not compile {entry_point} in {path:?}:\n{message}")]
+    ShaderCompilation {
+        path: PathBuf,
+        entry_point: String,
+        message: String,
+    },
+    /// No device supports what the renderer needs. Lists what each device lacks.
+    #[error("Missing device features:\n{0}")]
+    MissingFeatures(String),
+    #[error("Could not read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Validation warnings and errors collected while
+    /// [`crate::debug::DebugConfig::collect`] was enabled.
+    #[error("{} validation messages:\n{}", .0.len(), list_messages(.0))]
+    Validation(Vec<crate::debug::DebugMessage>),
+    #[error("Invalid shader manifest {path:?}: {message}")]
+    Manifest { path: PathBuf, message: String },
+    /// The compiled shaders do not fit together or use unsupported resources.
+    #[error("Shader reflection failed: {0}")]
+    Reflection(String),
+    #[error("Could not watch shader sources: {0}")]
+    Watch(notify::Error),
+    #[error("Allocation failed: {0}")]
+    Allocation(gpu_allocator::AllocationError),
+    /// A value does not fit the buffer it is written to or read from.
+    #[error("Buffer has {found} bytes, {expected} are needed")]
+    BufferTooSmall { expected: u64, found: u64 },
+    /// Persistent buffers are restored from the host, see
+    /// [`crate::renderer::Renderer::create_persistent_buffer`].
+    #[error("Persistent buffer {name} has to be host visible")]
+    NotHostVisible { name: String },
+    /// Shader test inputs and outputs are addressed by their size, see
+    /// [`crate::shader_test::ShaderFunction::run`].
+    #[error("{type_name} is zero-sized")]
+    ZeroSized { type_name: &'static str },
+    /// A compute program was bound for drawing or a graphics program for
+    /// dispatching.
+    #[error("{name} is {}a compute program", if *.compute { "" } else { "not " })]
+    ProgramKind { name: String, compute: bool },
+    #[error("Vulkan call failed: {0}")]
+    Vulkan(vk::Result),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+fn list_messages(messages: &[crate::debug::DebugMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| format!("  {message}\n"))
+        .collect()
+}
+
+impl From<vk::Result> for Error {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => {
+                Error::SwapchainOutOfDate
+            }
+            vk::Result::ERROR_SURFACE_LOST_KHR => Error::SurfaceLost,
+            vk::Result::ERROR_DEVICE_LOST => Error::DeviceLost,
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => {
+                Error::OutOfMemory
+            }
+            result => Error::Vulkan(result),
+        }
+    }
+}
+
+impl From<gpu_allocator::AllocationError> for Error {
+    fn from(error: gpu_allocator::AllocationError) -> Self {
+        match error {
+            gpu_allocator::AllocationError::OutOfMemory => Error::OutOfMemory,
+            error => Error::Allocation(error),
+        }
+    }
+}
+
+impl From<encase::internal::Error> for Error {
+    fn from(error: encase::internal::Error) -> Self {
+        match error {
+            encase::internal::Error::BufferTooSmall { expected, found } => {
+                Error::BufferTooSmall { expected, found }
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use naga::{back::spv, front::glsl, valid};
+
+use crate::{
+    error::{Error, Result},
+    manifest::{Stage, StageDesc},
+    shader_cache::{CompileOutput, CompileRequest, ShaderCache},
+};
+
+/// Language of a stage's source, chosen by its file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceLanguage {
+    /// `.hlsl`, compiled by DXC through the [`ShaderCache`].
+    Hlsl,
+    /// `.glsl`, `.vert`, `.frag` and `.comp`, compiled by naga.
+    Glsl,
+    /// `.wgsl`, compiled by naga.
+    Wgsl,
+    /// `.spv`, loaded as is.
+    Spirv,
+}
+
+impl SourceLanguage {
+    pub fn from_path(path: &Path) -> Option<SourceLanguage> {
+        match path.extension()?.to_str()? {
+            "hlsl" => Some(SourceLanguage::Hlsl),
+            "glsl" | "vert" | "frag" | "comp" => Some(SourceLanguage::Glsl),
+            "wgsl" => Some(SourceLanguage::Wgsl),
+            "spv" => Some(SourceLanguage::Spirv),
+            _ => None,
+        }
+    }
+
+    /// Whether the language's compiler takes a profile and DXC arguments.
+    pub fn is_hlsl(self) -> bool {
+        self == SourceLanguage::Hlsl
+    }
+
+    pub fn supports_defines(self) -> bool {
+        matches!(self, SourceLanguage::Hlsl | SourceLanguage::Glsl)
+    }
+
+    /// naga has no task or mesh stages.
+    pub fn supports_stage(self, stage: Stage) -> bool {
+        !matches!(
+            (self, stage),
+            (SourceLanguage::Glsl | SourceLanguage::Wgsl, Stage::Task | Stage::Mesh)
+        )
+    }
+}
+
+/// Compiles the SPIR-V of one stage, whose source is relative to `directory`.
+/// Only HLSL goes through `cache` and resolves `#include`s, also in
+/// `include_paths` relative to `directory`. naga is fast enough to run on
+/// every load.
+pub fn compile_stage(
+    directory: &Path,
+    include_paths: &[PathBuf],
+    stage: &StageDesc,
+    cache: &ShaderCache,
+) -> Result<CompileOutput> {
+    let path = directory.join(&stage.source);
+    let io_error = |source| Error::Io {
+        path: path.clone(),
+        source,
+    };
+    let Some(language) = SourceLanguage::from_path(&path) else {
+        return Err(compilation_error(&path, stage, "Unknown source language".to_owned()));
+    };
+    if language == SourceLanguage::Spirv {
+        return Ok(CompileOutput {
+            spirv: fs::read(&path).map_err(io_error)?,
+            includes: Vec::new(),
+        });
+    }
+    let source = fs::read_to_string(&path).map_err(io_error)?;
+
+    if language.is_hlsl() {
+        let defines: Vec<(&str, Option<&str>)> = stage
+            .defines
+            .iter()
+            .map(|(name, value)| (name.as_str(), (!value.is_empty()).then_some(value.as_str())))
+            .collect();
+        let include_dirs: Vec<String> = include_paths
+            .iter()
+            .map(|include_path| directory.join(include_path).to_string_lossy().into_owned())
+            .collect();
+        let args: Vec<&str> = std::iter::once("-spirv")
+            .chain(include_dirs.iter().flat_map(|dir| ["-I", dir.as_str()]))
+            .chain(stage.args.iter().map(String::as_str))
+            .collect();
+
+        cache.compile(&CompileRequest {
+            directory: &directory.to_string_lossy(),
+            path: &path.to_string_lossy(),
+            source: &source,
+            entry_point: &stage.entry_point,
+            profile: &stage.profile,
+            args: &args,
+            defines: &defines,
+        })
+    } else {
+        Ok(CompileOutput {
+            spirv: compile_naga(&path, &source, language, stage)?,
+            includes: Vec::new(),
+        })
+    }
+}
+
+fn compile_naga(path: &Path, source: &str, language: SourceLanguage, stage: &StageDesc) -> Result<Vec<u8>> {
+    let error = |message| compilation_error(path, stage, message);
+    let path_name = path.to_string_lossy();
+
+    let shader_stage = match stage.stage {
+        Stage::Vertex => naga::ShaderStage::Vertex,
+        Stage::Fragment => naga::ShaderStage::Fragment,
+        Stage::Compute => naga::ShaderStage::Compute,
+        Stage::Task | Stage::Mesh => return Err(error("naga has no task or mesh stages".to_owned())),
+    };
+
+    let module = if language == SourceLanguage::Glsl {
+        let options = glsl::Options {
+            stage: shader_stage,
+            defines: stage
+                .defines
+                .iter()
+                .map(|(name, value)| {
+                    let value = if value.is_empty() { "1" } else { value };
+                    (name.clone(), value.to_owned())
+                })
+                .collect(),
+        };
+        glsl::Frontend::default()
+            .parse(&options, source)
+            .map_err(|errors| {
+                let message = errors
+                    .iter()
+                    .map(|glsl_error| {
+                        let location = glsl_error.meta.location(source);
+                        format!(
+                            "{path_name}:{}:{}: error: {glsl_error}\n",
+                            location.line_number, location.line_position
+                        )
+                    })
+                    .collect();
+                error(message)
+            })?
+    } else {
+        naga::front::wgsl::parse_str(source)
+            .map_err(|parse_error| error(parse_error.emit_to_string_with_path(source, &path_name)))?
+    };
+
+    let info = valid::Validator::new(valid::ValidationFlags::all(), valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|validation_error| error(validation_error.emit_to_string_with_path(source, &path_name)))?;
+
+    // WGSL clip space has y pointing up like the web APIs, GLSL shaders are
+    // written against Vulkan's and are left as they are.
+    let mut options = spv::Options {
+        lang_version: (1, 5),
+        ..Default::default()
+    };
+    if language == SourceLanguage::Glsl {
+        options.flags.remove(spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    }
+    let pipeline_options = spv::PipelineOptions {
+        shader_stage,
+        entry_point: stage.entry_point.clone(),
+    };
+
+    let words = spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+        .map_err(|write_error| error(write_error.to_string()))?;
+    Ok(bytemuck::cast_slice(&words).to_vec())
+}
+
+fn compilation_error(path: &Path, stage: &StageDesc, message: String) -> Error {
+    Error::ShaderCompilation {
+        path: path.to_owned(),
+        entry_point: stage.entry_point.clone(),
+        message,
+    }
+}
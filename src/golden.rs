@@ -0,0 +1,251 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    app::{App, AppConfig},
+    renderer::Clock,
+};
+
+/// Environment variable that makes failing or missing references get replaced
+/// by the rendered image instead of failing the test. Without it nothing is
+/// written to the reference directory.
+pub const UPDATE_ENV: &str = "GOLDEN_UPDATE";
+
+/// Tightly packed RGBA8 image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn load_png(path: &Path) -> Result<Image> {
+        let file = File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        buffer.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            color_type => bail!("Unsupported color type {:?} in {:?}", color_type, path),
+        };
+
+        Ok(Image {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path).with_context(|| format!("Could not create {:?}", path))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+
+        Ok(())
+    }
+}
+
+pub struct Comparison {
+    /// Pixels where at least one channel differs by more than the tolerance.
+    pub mismatched_pixels: usize,
+    /// Largest difference of a single channel over the whole image.
+    pub max_difference: u8,
+    /// Mismatched pixels in red over a dimmed copy of the expected image.
+    pub diff: Image,
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.mismatched_pixels == 0
+    }
+}
+
+/// Compares two images channel by channel. Images of different sizes never
+/// match; every pixel is then reported as mismatched.
+pub fn compare(actual: &Image, expected: &Image, tolerance: u8) -> Comparison {
+    if actual.width != expected.width || actual.height != expected.height {
+        return Comparison {
+            mismatched_pixels: (actual.width * actual.height).max(expected.width * expected.height)
+                as usize,
+            max_difference: 255,
+            diff: Image {
+                width: actual.width,
+                height: actual.height,
+                pixels: [255, 0, 0, 255].repeat((actual.width * actual.height) as usize),
+            },
+        };
+    }
+
+    let mut mismatched_pixels = 0;
+    let mut max_difference = 0;
+    let mut diff = Vec::with_capacity(expected.pixels.len());
+
+    for (a, e) in actual.pixels.chunks_exact(4).zip(expected.pixels.chunks_exact(4)) {
+        let difference = a
+            .iter()
+            .zip(e)
+            .map(|(a, e)| a.abs_diff(*e))
+            .max()
+            .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+
+        if difference > tolerance {
+            mismatched_pixels += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            diff.extend_from_slice(&[e[0] / 4, e[1] / 4, e[2] / 4, 255]);
+        }
+    }
+
+    Comparison {
+        mismatched_pixels,
+        max_difference,
+        diff: Image {
+            width: expected.width,
+            height: expected.height,
+            pixels: diff,
+        },
+    }
+}
+
+/// Renders a fixed number of frames headlessly with a deterministic clock and
+/// compares the final frame against `<reference_dir>/<name>.png`. Any
+/// validation warning or error fails the test.
+///
+/// On a mismatch the actual, expected and diff images are written to
+/// `<output_dir>/<name>.{actual,expected,diff}.png`. A missing reference fails
+/// too, with only the actual image written.
+pub struct GoldenTest {
+    pub name: String,
+
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub clock: Clock,
+
+    /// Largest per-channel difference that still counts as a match.
+    pub tolerance: u8,
+
+    pub reference_dir: PathBuf,
+    pub output_dir: PathBuf,
+}
+
+impl GoldenTest {
+    pub fn new(name: &str) -> GoldenTest {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+        GoldenTest {
+            name: name.to_owned(),
+
+            width: 256,
+            height: 256,
+            frames: 3,
+            clock: Clock::Fixed {
+                start: Duration::ZERO,
+                step: Duration::from_millis(16),
+            },
+
+            tolerance: 2,
+
+            reference_dir: root.join("tests").join("golden"),
+            output_dir: root.join("target").join("golden"),
+        }
+    }
+
+    pub fn render(&self) -> Result<Image> {
+        let mut config = AppConfig::from_env_and_args(std::iter::empty())?;
+        config.debug.collect = true;
+        config.validation.synchronization = true;
+        let mut app = App::new_headless(self.width, self.height, &config)
+            .with_context(|| "Could not create headless app.")?;
+        app.renderer.set_clock(self.clock);
+
+        for frame in 0..self.frames {
+            app.render()?;
+            app.check_validation()
+                .with_context(|| format!("Frame {} produced validation messages.", frame))?;
+        }
+
+        let pixels = app
+            .read_pixels()?
+            .with_context(|| "Headless app did not render offscreen.")?;
+        app.check_validation()
+            .with_context(|| "Reading back the frame produced validation messages.")?;
+
+        Ok(Image {
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let actual = self.render()?;
+
+        let reference_path = self.reference_dir.join(format!("{}.png", self.name));
+        let update = std::env::var_os(UPDATE_ENV).is_some();
+
+        let output = |kind: &str| self.output_dir.join(format!("{}.{}.png", self.name, kind));
+
+        if !reference_path.exists() {
+            if update {
+                return actual.save_png(&reference_path);
+            }
+            actual.save_png(&output("actual"))?;
+            bail!(
+                "Missing reference {:?}. The rendered image is at {:?}, review it and run with {}=1 to commit it.",
+                reference_path,
+                output("actual"),
+                UPDATE_ENV
+            );
+        }
+
+        let expected = Image::load_png(&reference_path)?;
+        let comparison = compare(&actual, &expected, self.tolerance);
+        if comparison.passed() {
+            return Ok(());
+        }
+
+        if update {
+            actual.save_png(&reference_path)?;
+            return Ok(());
+        }
+
+        actual.save_png(&output("actual"))?;
+        expected.save_png(&output("expected"))?;
+        comparison.diff.save_png(&output("diff"))?;
+
+        bail!(
+            "{}: {} pixels differ by more than {} (max difference {}). See {:?}.",
+            self.name,
+            comparison.mismatched_pixels,
+            self.tolerance,
+            comparison.max_difference,
+            output("diff")
+        )
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{
+    error::{Error, Result},
+    shaders::ShaderCode,
+};
+
+/// Editors often write a file in several steps. Events closer together than
+/// this are compiled once.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watches the shader directory and every included file, and recompiles the
+/// shaders on a background thread whenever a source, an include or the
+/// manifest changes. Compile errors are logged and skipped, so
+/// [`ShaderWatcher::poll`] only ever returns code that compiled.
+pub struct ShaderWatcher {
+    // Dropping the watcher closes the event channel, which ends the thread.
+    // The thread only holds a weak reference, to watch new include directories.
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
+    receiver: Receiver<ShaderCode>,
+}
+
+impl ShaderWatcher {
+    /// `dependencies` are those of the running shaders, see
+    /// [`crate::shaders::Shaders::dependencies`]. Later ones are taken from
+    /// each recompilation.
+    pub fn new(directory: &Path, dependencies: &BTreeSet<PathBuf>) -> Result<ShaderWatcher> {
+        let (event_sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(event_sender).map_err(Error::Watch)?;
+        watcher
+            .watch(directory, RecursiveMode::Recursive)
+            .map_err(Error::Watch)?;
+
+        let mut tracked = Dependencies {
+            directory: canonical(directory),
+            files: HashSet::new(),
+            directories: HashSet::new(),
+        };
+        tracked.update(&mut watcher, dependencies);
+        let watcher = Arc::new(Mutex::new(watcher));
+        let weak_watcher = Arc::downgrade(&watcher);
+
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("shader-watcher".to_owned())
+            .spawn(move || {
+                while let Ok(event) = events.recv() {
+                    if !tracked.is_change(event) {
+                        continue;
+                    }
+
+                    // Wait until the burst of events is over.
+                    loop {
+                        match events.recv_timeout(DEBOUNCE) {
+                            Ok(_) => continue,
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        }
+                    }
+
+                    match ShaderCode::compile() {
+                        Ok(code) => {
+                            log::info!("Shaders recompiled");
+                            let Some(watcher) = weak_watcher.upgrade() else {
+                                return;
+                            };
+                            if let Ok(mut watcher) = watcher.lock() {
+                                tracked.update(&mut watcher, &code.dependencies());
+                            }
+                            if sender.send(code).is_err() {
+                                return;
+                            }
+                        }
+                        Err(error) => log::error!("Keeping previous shaders. {error}"),
+                    }
+                }
+            })
+            .map_err(|source| Error::Io {
+                path: directory.to_owned(),
+                source,
+            })?;
+
+        Ok(ShaderWatcher {
+            _watcher: watcher,
+            receiver,
+        })
+    }
+
+    /// Latest code compiled since the last call, older results are skipped.
+    pub fn poll(&self) -> Option<ShaderCode> {
+        self.receiver.try_iter().last()
+    }
+}
+
+/// Files the shaders were compiled from and the directories watched for those
+/// outside the shader directory. All paths are canonical.
+struct Dependencies {
+    directory: PathBuf,
+    files: HashSet<PathBuf>,
+    directories: HashSet<PathBuf>,
+}
+
+impl Dependencies {
+    /// Replaces the files and watches the directories of new ones. Directories
+    /// are never unwatched, they only cost an occasional spurious event.
+    fn update(&mut self, watcher: &mut RecommendedWatcher, dependencies: &BTreeSet<PathBuf>) {
+        self.files = dependencies.iter().map(|path| canonical(path)).collect();
+
+        for file in &self.files {
+            let Some(parent) = file.parent() else {
+                continue;
+            };
+            if parent.starts_with(&self.directory) || self.directories.contains(parent) {
+                continue;
+            }
+            match watcher.watch(parent, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    self.directories.insert(parent.to_owned());
+                }
+                Err(error) => log::warn!("Could not watch includes in {parent:?}: {error}"),
+            }
+        }
+    }
+
+    /// Whether `event` changed a dependency, or a source or manifest that is
+    /// not compiled yet.
+    fn is_change(&self, event: notify::Result<notify::Event>) -> bool {
+        let Ok(event) = event else {
+            return false;
+        };
+
+        matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event.paths.iter().any(|path| {
+                self.files.contains(&canonical(path))
+                    || matches!(
+                        path.extension().and_then(|extension| extension.to_str()),
+                        Some("hlsl" | "hlsli" | "glsl" | "vert" | "frag" | "comp" | "wgsl" | "spv" | "toml")
+                    )
+            })
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
@@ -0,0 +1,18 @@
+pub mod app;
+pub mod debug;
+pub mod error;
+pub mod front_end;
+pub mod golden;
+pub mod hot_reload;
+pub mod manifest;
+pub mod offscreen;
+pub mod reflection;
+pub mod renderer;
+pub mod requirements_filters;
+pub mod surface;
+pub mod swapchain;
+pub mod shader_cache;
+pub mod shader_test;
+pub mod shared;
+pub mod shaders;
+pub mod typed_buffer;
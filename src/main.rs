@@ -1,12 +1,7 @@
-mod app;
-mod debug;
-mod renderer;
-mod requirements_filters;
-mod surface;
-mod swapchain;
-mod shaders;
-
-use crate::app::App;
+use vulkan_modern::{
+    app::{App, AppConfig},
+    error::Error,
+};
 
 use winit::{
     event::{Event, WindowEvent},
@@ -18,6 +13,11 @@ use std::{borrow::{Cow, Borrow}, ffi::CStr, fs::{File, OpenOptions}, io::Write};
 use anyhow::{Result, Context};
 
 fn main() -> Result<()> {
+    // Validation messages are logged under the `vulkan` target.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = AppConfig::from_env_and_args(std::env::args().skip(1))?;
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -25,6 +25,7 @@ fn main() -> Result<()> {
         &window,
         window.inner_size().width,
         window.inner_size().height,
+        &config,
     ).with_context(|| "Could not create app.")?;
     let mut dirty_swapchain = false;
 
@@ -55,12 +56,28 @@ fn main() -> Result<()> {
                     if dirty_swapchain {
                         let size = window.inner_size();
                         if size.width > 0 && size.height > 0 {
-                            app.recreate_swapchain(size.width, size.height);
+                            if let Err(error) = app.recreate_swapchain(size.width, size.height) {
+                                eprintln!("Could not recreate swapchain: {error}");
+                                *control_flow = ControlFlow::Exit;
+                                return;
+                            }
                         } else {
                             return;
                         }
                     }
-                    dirty_swapchain = app.render();
+                    dirty_swapchain = match app.render() {
+                        Ok(None) => false,
+                        Ok(Some(recovery)) => {
+                            eprintln!("Recovered from {recovery:?}");
+                            false
+                        }
+                        Err(Error::SwapchainOutOfDate) => true,
+                        Err(error) => {
+                            eprintln!("Could not render frame: {error}");
+                            *control_flow = ControlFlow::Exit;
+                            false
+                        }
+                    };
                 }
             }
             // Event::LoopDestroyed => app.wait_gpu_idle(),
@@ -0,0 +1,381 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    front_end::SourceLanguage,
+};
+
+/// File in [`crate::shaders::SHADER_DIR`] that lists the shader programs.
+pub const MANIFEST_FILE: &str = "shaders.toml";
+
+/// Pipeline stage of one entry point. Graphics stages are declared in pipeline
+/// order, the stages of a program have to be listed in this order. A program
+/// draws either with a vertex stage or with mesh stages, and a compute stage is
+/// always a program of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Vertex,
+    Task,
+    Mesh,
+    Fragment,
+    Compute,
+}
+
+impl Stage {
+    pub fn flags(self) -> vk::ShaderStageFlags {
+        match self {
+            Stage::Vertex => vk::ShaderStageFlags::VERTEX,
+            Stage::Task => vk::ShaderStageFlags::TASK_EXT,
+            Stage::Mesh => vk::ShaderStageFlags::MESH_EXT,
+            Stage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            Stage::Compute => vk::ShaderStageFlags::COMPUTE,
+        }
+    }
+}
+
+/// One entry point of a program.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StageDesc {
+    pub stage: Stage,
+    /// Relative to the manifest's directory, its extension selects the
+    /// [`SourceLanguage`].
+    pub source: PathBuf,
+    /// Always `main` for GLSL.
+    pub entry_point: String,
+    /// DXC target profile, only for HLSL.
+    #[serde(default)]
+    pub profile: String,
+    /// Preprocessor defines for HLSL and GLSL, an empty value defines the name
+    /// as `1`.
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+    /// Passed to DXC after `-spirv`, e.g. `-fspv-target-env=vulkan1.3`.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Define that selects between variants of a program. Without values it is a
+/// flag that is either not defined or defined as `1`, otherwise it is defined
+/// as one of the values, the first one by default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisDesc {
+    pub define: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+impl AxisDesc {
+    pub fn is_flag(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Specialization constant of every stage of a program.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConstantDesc {
+    pub name: String,
+    /// `[[vk::constant_id(id)]]` in HLSL, `layout(constant_id = id)` in GLSL.
+    pub id: u32,
+    /// Also gives the constant its type.
+    pub default: ConstantValue,
+}
+
+/// Value of a specialization constant. Integers cover both `int` and `uint`
+/// constants, floats are passed as `float`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ConstantValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+impl ConstantValue {
+    /// The 4 bytes of specialization data Vulkan expects for the value.
+    pub fn bits(self) -> u32 {
+        match self {
+            ConstantValue::Bool(value) => value as u32,
+            ConstantValue::Int(value) => value as u32,
+            ConstantValue::Float(value) => (value as f32).to_bits(),
+        }
+    }
+
+    pub fn same_type(self, other: ConstantValue) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+}
+
+// Values are compared as the data that reaches the shader.
+impl PartialEq for ConstantValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_type(*other) && self.bits() == other.bits()
+    }
+}
+
+impl Eq for ConstantValue {}
+
+impl Hash for ConstantValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        self.bits().hash(state);
+    }
+}
+
+impl From<bool> for ConstantValue {
+    fn from(value: bool) -> Self {
+        ConstantValue::Bool(value)
+    }
+}
+
+impl From<i32> for ConstantValue {
+    fn from(value: i32) -> Self {
+        ConstantValue::Int(value.into())
+    }
+}
+
+impl From<u32> for ConstantValue {
+    fn from(value: u32) -> Self {
+        ConstantValue::Int(value.into())
+    }
+}
+
+impl From<f32> for ConstantValue {
+    fn from(value: f32) -> Self {
+        ConstantValue::Float(value.into())
+    }
+}
+
+/// Named set of stages that are linked and bound together.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProgramDesc {
+    pub name: String,
+    #[serde(rename = "stage")]
+    pub stages: Vec<StageDesc>,
+    /// Program used instead on devices without mesh shaders. Only for
+    /// programs with mesh stages.
+    pub fallback: Option<String>,
+    /// Each combination of axis values is a variant compiled from the same
+    /// sources, see [`crate::shaders::PermutationKey`].
+    #[serde(rename = "axis", default)]
+    pub axes: Vec<AxisDesc>,
+    #[serde(rename = "constant", default)]
+    pub constants: Vec<ConstantDesc>,
+}
+
+impl ProgramDesc {
+    pub fn has_mesh_stages(&self) -> bool {
+        self.stages
+            .iter()
+            .any(|stage| matches!(stage.stage, Stage::Task | Stage::Mesh))
+    }
+
+    pub fn axis(&self, define: &str) -> Option<&AxisDesc> {
+        self.axes.iter().find(|axis| axis.define == define)
+    }
+
+    pub fn constant(&self, name: &str) -> Option<&ConstantDesc> {
+        self.constants.iter().find(|constant| constant.name == name)
+    }
+
+    /// The program with `defines` added to every stage, for compiling one of
+    /// its variants.
+    pub fn with_defines(&self, defines: &BTreeMap<String, String>) -> ProgramDesc {
+        let mut desc = self.clone();
+        for stage in &mut desc.stages {
+            stage.defines.extend(defines.clone());
+        }
+        desc
+    }
+}
+
+/// Parsed shader manifest, e.g.
+///
+/// ```toml
+/// include_paths = ["common"]
+///
+/// [[program]]
+/// name = "triangle"
+///
+/// [[program.stage]]
+/// stage = "vertex"
+/// source = "triangle.hlsl"
+/// entry_point = "vertexMain"
+/// profile = "vs_6_6"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Directories searched for HLSL `#include`s after the including file's
+    /// own, relative to the manifest's directory.
+    #[serde(default)]
+    pub include_paths: Vec<PathBuf>,
+    #[serde(rename = "program", default)]
+    pub programs: Vec<ProgramDesc>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let text = fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::parse(&text).map_err(|message| Error::Manifest {
+            path: path.to_owned(),
+            message,
+        })
+    }
+
+    /// Parses and validates a manifest. Program names have to be unique and
+    /// every program needs at least one stage, each listed once and in
+    /// pipeline order. Compute stages cannot be combined with other stages,
+    /// task stages need a mesh stage and vertex stages cannot be combined with
+    /// either. Fallbacks have to name a program without mesh stages. Each
+    /// source needs a known language that supports its stage and settings.
+    /// Axes and constants need unique names, and axes sources with defines.
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let manifest: Manifest = toml::from_str(text).map_err(|error| error.to_string())?;
+
+        let mut names = HashSet::new();
+        for program in &manifest.programs {
+            if !names.insert(program.name.as_str()) {
+                return Err(format!("Program {:?} is declared twice", program.name));
+            }
+            if program.stages.is_empty() {
+                return Err(format!("Program {:?} has no stages", program.name));
+            }
+            if program.stages.len() > 1
+                && program.stages.iter().any(|stage| stage.stage == Stage::Compute)
+            {
+                return Err(format!(
+                    "Program {:?} combines a compute stage with other stages",
+                    program.name
+                ));
+            }
+            let has = |stage| program.stages.iter().any(|desc| desc.stage == stage);
+            if has(Stage::Vertex) && program.has_mesh_stages() {
+                return Err(format!(
+                    "Program {:?} combines a vertex stage with mesh stages",
+                    program.name
+                ));
+            }
+            if has(Stage::Task) && !has(Stage::Mesh) {
+                return Err(format!("Program {:?} has a task stage but no mesh stage", program.name));
+            }
+            for stage in &program.stages {
+                check_source(stage).map_err(|message| {
+                    format!("Program {:?} {:?} stage: {message}", program.name, stage.stage)
+                })?;
+            }
+            check_permutations(program).map_err(|message| format!("Program {:?}: {message}", program.name))?;
+            for pair in program.stages.windows(2) {
+                if pair[0].stage >= pair[1].stage {
+                    return Err(format!(
+                        "Program {:?} lists {:?} after {:?}, stages have to be unique and in pipeline order",
+                        program.name, pair[1].stage, pair[0].stage
+                    ));
+                }
+            }
+        }
+
+        for program in &manifest.programs {
+            let Some(fallback) = &program.fallback else {
+                continue;
+            };
+            if !program.has_mesh_stages() {
+                return Err(format!(
+                    "Program {:?} has a fallback but no mesh stages",
+                    program.name
+                ));
+            }
+            match manifest.program(fallback) {
+                Some(fallback) if !fallback.has_mesh_stages() => {}
+                Some(_) => {
+                    return Err(format!(
+                        "Fallback {fallback:?} of {:?} has mesh stages itself",
+                        program.name
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "Fallback {fallback:?} of {:?} does not exist",
+                        program.name
+                    ))
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn program(&self, name: &str) -> Option<&ProgramDesc> {
+        self.programs.iter().find(|program| program.name == name)
+    }
+}
+
+fn check_source(stage: &StageDesc) -> Result<(), String> {
+    let Some(language) = SourceLanguage::from_path(&stage.source) else {
+        return Err(format!("{:?} is not in a known shader language", stage.source));
+    };
+    if !language.supports_stage(stage.stage) {
+        return Err(format!("{language:?} has no {:?} stages", stage.stage));
+    }
+    if language.is_hlsl() && stage.profile.is_empty() {
+        return Err("HLSL needs a profile".to_owned());
+    }
+    if !language.is_hlsl() && (!stage.profile.is_empty() || !stage.args.is_empty()) {
+        return Err(format!("{language:?} takes no profile or DXC arguments"));
+    }
+    if !language.supports_defines() && !stage.defines.is_empty() {
+        return Err(format!("{language:?} takes no defines"));
+    }
+    Ok(())
+}
+
+fn check_permutations(program: &ProgramDesc) -> Result<(), String> {
+    let mut defines = HashSet::new();
+    for axis in &program.axes {
+        if !defines.insert(axis.define.as_str()) {
+            return Err(format!("Axis {:?} is declared twice", axis.define));
+        }
+        let mut values = HashSet::new();
+        if axis.values.iter().any(|value| value.is_empty() || !values.insert(value)) {
+            return Err(format!("Axis {:?} has empty or repeated values", axis.define));
+        }
+        for stage in &program.stages {
+            if stage.defines.contains_key(&axis.define) {
+                return Err(format!("Axis {:?} is also a define of the {:?} stage", axis.define, stage.stage));
+            }
+            if !SourceLanguage::from_path(&stage.source).is_some_and(SourceLanguage::supports_defines) {
+                return Err(format!("Axis {:?} needs defines, {:?} has none", axis.define, stage.source));
+            }
+        }
+    }
+
+    let mut names = HashSet::new();
+    let mut ids = HashSet::new();
+    for constant in &program.constants {
+        if !names.insert(constant.name.as_str()) || !ids.insert(constant.id) {
+            return Err(format!("Constant {:?} reuses a name or id", constant.name));
+        }
+        if let ConstantValue::Int(value) = constant.default {
+            if !(i64::from(i32::MIN)..=i64::from(u32::MAX)).contains(&value) {
+                return Err(format!("Constant {:?} does not fit 32 bits", constant.name));
+            }
+        }
+    }
+
+    Ok(())
+}
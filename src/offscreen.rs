@@ -0,0 +1,106 @@
+use ash::vk;
+use gpu_allocator::{vulkan::*, MemoryLocation};
+
+use crate::{debug::DebugNames, error::Result};
+
+/// Color image that the renderer draws into when there is no swapchain.
+pub struct Offscreen {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub allocation: Allocation,
+
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Offscreen {
+    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+    pub fn new(
+        device: &ash::Device,
+        allocator: &mut Allocator,
+        debug: &DebugNames,
+        width: u32,
+        height: u32,
+    ) -> Result<Offscreen> {
+        unsafe {
+            let image = device
+                .create_image(
+                    &vk::ImageCreateInfo::builder()
+                        .image_type(vk::ImageType::TYPE_2D)
+                        .format(Self::FORMAT)
+                        .extent(vk::Extent3D {
+                            width,
+                            height,
+                            depth: 1,
+                        })
+                        .mip_levels(1)
+                        .array_layers(1)
+                        .samples(vk::SampleCountFlags::TYPE_1)
+                        .tiling(vk::ImageTiling::OPTIMAL)
+                        .usage(
+                            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                                | vk::ImageUsageFlags::TRANSFER_SRC,
+                        )
+                        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                        .initial_layout(vk::ImageLayout::UNDEFINED)
+                        .build(),
+                    None,
+                )?;
+
+            debug.name(image, "Offscreen");
+
+            let requirements = device.get_image_memory_requirements(image);
+
+            let allocation = allocator
+                .allocate(&AllocationCreateDesc {
+                    name: "Offscreen",
+                    requirements,
+                    location: MemoryLocation::GpuOnly,
+                    linear: false,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                })?;
+
+            device.bind_image_memory(image, allocation.memory(), allocation.offset())?;
+
+            let view = device
+                .create_image_view(
+                    &vk::ImageViewCreateInfo::builder()
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(Self::FORMAT)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .image(image)
+                        .build(),
+                    None,
+                )?;
+            debug.name(view, "Offscreen view");
+
+            Ok(Offscreen {
+                image,
+                view,
+                allocation,
+
+                format: Self::FORMAT,
+                width,
+                height,
+            })
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+        }
+        self.view = vk::ImageView::null();
+        self.image = vk::Image::null();
+        allocator.free(std::mem::take(&mut self.allocation)).unwrap();
+    }
+}
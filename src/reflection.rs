@@ -0,0 +1,661 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Cursor,
+};
+
+use ash::vk;
+use rspirv::{
+    dr::{Instruction, Module, Operand},
+    grammar::{CoreInstructionTable, OperandKind},
+    spirv::{Decoration, Dim, ExecutionModel, Op, StorageClass},
+};
+
+use crate::error::{Error, Result};
+
+/// Descriptor count used for runtime arrays (`Texture2D textures[]`).
+pub const UNBOUNDED_DESCRIPTOR_COUNT: u32 = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    /// Name of the variable, if the module kept debug names.
+    pub name: Option<String>,
+}
+
+/// Member of a block, e.g. of the push constants.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// Name of the member, if the module kept debug names.
+    pub name: Option<String>,
+    pub offset: u32,
+}
+
+/// Interface of one compiled shader module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reflection {
+    pub entry_points: Vec<EntryPoint>,
+    /// Bytes of the push constant block, 0 when there is none.
+    pub push_constant_size: u32,
+    /// Top-level members of the push constant block, in declaration order.
+    pub push_constant_members: Vec<Member>,
+    pub bindings: Vec<Binding>,
+}
+
+impl Reflection {
+    /// Reflects SPIR-V as produced by DXC.
+    pub fn new(spirv: &[u8]) -> Result<Reflection> {
+        let module = rspirv::dr::load_words(declarations(spirv)?)
+            .map_err(|error| Error::Reflection(format!("Invalid SPIR-V: {error:?}")))?;
+        let types = Types::new(&module);
+
+        let entry_points = module
+            .entry_points
+            .iter()
+            .map(|instruction| {
+                let (Some(Operand::ExecutionModel(model)), Some(Operand::LiteralString(name))) =
+                    (instruction.operands.first(), instruction.operands.get(2))
+                else {
+                    return Err(Error::Reflection("Malformed OpEntryPoint".to_owned()));
+                };
+                Ok(EntryPoint {
+                    name: name.clone(),
+                    stage: stage(*model)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut push_constant_size = 0;
+        let mut push_constant_members = Vec::new();
+        let mut bindings = Vec::new();
+        for variable in module
+            .types_global_values
+            .iter()
+            .filter(|instruction| instruction.class.opcode == Op::Variable)
+        {
+            let (Some(id), Some(Operand::StorageClass(storage_class))) =
+                (variable.result_id, variable.operands.first())
+            else {
+                continue;
+            };
+            let Some(pointee) = variable.result_type.and_then(|pointer| types.pointee(pointer))
+            else {
+                continue;
+            };
+
+            match storage_class {
+                StorageClass::PushConstant => {
+                    push_constant_size = push_constant_size.max(types.size(pointee)?);
+                    push_constant_members = types.members(pointee);
+                }
+                StorageClass::UniformConstant | StorageClass::Uniform | StorageClass::StorageBuffer => {
+                    let (Some(set), Some(binding)) = (
+                        types.decoration(id, Decoration::DescriptorSet),
+                        types.decoration(id, Decoration::Binding),
+                    ) else {
+                        continue;
+                    };
+                    let (element, count) = types.array_element(pointee);
+                    bindings.push(Binding {
+                        set,
+                        binding,
+                        descriptor_type: types.descriptor_type(*storage_class, element)?,
+                        count,
+                        name: types.names.get(&id).cloned(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Reflection {
+            entry_points,
+            push_constant_size,
+            push_constant_members,
+            bindings,
+        })
+    }
+
+    pub fn stages(&self) -> vk::ShaderStageFlags {
+        self.entry_points
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stages, entry_point| stages | entry_point.stage)
+    }
+}
+
+/// Pipeline layout shared by a set of linked stages.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderLayout {
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+    /// Bindings of each descriptor set, with the stages that use them.
+    pub sets: BTreeMap<u32, Vec<vk::DescriptorSetLayoutBinding>>,
+}
+
+impl ShaderLayout {
+    /// Merges the interfaces of stages that are linked together. Stages that
+    /// disagree on the size of the push constant block, or on the type or count
+    /// of a binding, are an error.
+    pub fn merge(stages: &[Reflection]) -> Result<ShaderLayout> {
+        let mut push_constants: Option<(u32, vk::ShaderStageFlags, &str)> = None;
+        let mut sets: BTreeMap<u32, Vec<vk::DescriptorSetLayoutBinding>> = BTreeMap::new();
+        let mut binding_users: HashMap<(u32, u32), &str> = HashMap::new();
+
+        for reflection in stages {
+            let stage_flags = reflection.stages();
+            let name = reflection
+                .entry_points
+                .first()
+                .map_or("<unnamed>", |entry_point| entry_point.name.as_str());
+
+            if reflection.push_constant_size > 0 {
+                push_constants = match push_constants {
+                    None => Some((reflection.push_constant_size, stage_flags, name)),
+                    Some((size, stages, other)) if size == reflection.push_constant_size => {
+                        Some((size, stages | stage_flags, other))
+                    }
+                    Some((size, _, other)) => {
+                        return Err(Error::Reflection(format!(
+                            "{other} uses {size} bytes of push constants but {name} uses {}",
+                            reflection.push_constant_size
+                        )))
+                    }
+                };
+            }
+
+            for binding in &reflection.bindings {
+                let set = sets.entry(binding.set).or_default();
+                match set.iter_mut().find(|b| b.binding == binding.binding) {
+                    Some(existing) => {
+                        if existing.descriptor_type != binding.descriptor_type
+                            || existing.descriptor_count != binding.count
+                        {
+                            return Err(Error::Reflection(format!(
+                                "Set {} binding {} is {} x {:?} in {} but {} x {:?} in {name}",
+                                binding.set,
+                                binding.binding,
+                                existing.descriptor_count,
+                                existing.descriptor_type,
+                                binding_users[&(binding.set, binding.binding)],
+                                binding.count,
+                                binding.descriptor_type,
+                            )));
+                        }
+                        existing.stage_flags |= stage_flags;
+                    }
+                    None => {
+                        binding_users.insert((binding.set, binding.binding), name);
+                        set.push(
+                            vk::DescriptorSetLayoutBinding::builder()
+                                .binding(binding.binding)
+                                .descriptor_type(binding.descriptor_type)
+                                .descriptor_count(binding.count)
+                                .stage_flags(stage_flags)
+                                .build(),
+                        );
+                    }
+                }
+            }
+        }
+
+        for set in sets.values_mut() {
+            set.sort_by_key(|binding| binding.binding);
+        }
+
+        Ok(ShaderLayout {
+            push_constant_ranges: push_constants
+                .map(|(size, stage_flags, _)| vk::PushConstantRange {
+                    stage_flags,
+                    offset: 0,
+                    size,
+                })
+                .into_iter()
+                .collect(),
+            sets,
+        })
+    }
+
+    /// Stages the push constants are visible to, empty without push constants.
+    pub fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        self.push_constant_ranges
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stages, range| stages | range.stage_flags)
+    }
+}
+
+/// Checks the structure of a module the way `spirv-val` starts out: the
+/// header, instruction lengths, result ids that are unique and below the
+/// bound, a memory model, and entry points that name functions. Opcodes newer
+/// than the grammar of rspirv are only checked for their length.
+pub fn validate(spirv: &[u8]) -> Result<()> {
+    const HEADER_WORDS: usize = 5;
+    let invalid = |message: String| Error::Reflection(format!("Invalid SPIR-V: {message}"));
+
+    let words = ash::util::read_spv(&mut Cursor::new(spirv)).map_err(|error| invalid(error.to_string()))?;
+    if words.len() < HEADER_WORDS {
+        return Err(invalid("truncated header".to_owned()));
+    }
+    let (major, minor) = ((words[1] >> 16) & 0xff, (words[1] >> 8) & 0xff);
+    if major != 1 || minor > 6 {
+        return Err(invalid(format!("unsupported version {major}.{minor}")));
+    }
+    let bound = words[3];
+    if words[4] != 0 {
+        return Err(invalid(format!("reserved schema word is {}", words[4])));
+    }
+
+    let mut results = HashSet::new();
+    let mut functions = HashSet::new();
+    let mut entry_points = Vec::new();
+    let mut memory_models = 0;
+    let mut capabilities = 0;
+    let mut in_function = false;
+    let mut offset = HEADER_WORDS;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        let opcode = words[offset] & 0xffff;
+        let Some(instruction) = words.get(offset..offset + word_count).filter(|_| word_count > 0)
+        else {
+            return Err(invalid(format!("truncated instruction at word {offset}")));
+        };
+        offset += word_count;
+
+        let result = CoreInstructionTable::lookup_opcode(opcode as u16).and_then(|grammar| {
+            match grammar.operands.first()?.kind {
+                OperandKind::IdResultType => instruction.get(2),
+                OperandKind::IdResult => instruction.get(1),
+                _ => None,
+            }
+        });
+        if let Some(&id) = result {
+            if id == 0 || id >= bound {
+                return Err(invalid(format!("result id {id} is outside of the bound {bound}")));
+            }
+            if !results.insert(id) {
+                return Err(invalid(format!("result id {id} is defined twice")));
+            }
+        }
+
+        match opcode {
+            op if op == Op::Capability as u32 => capabilities += 1,
+            op if op == Op::MemoryModel as u32 => memory_models += 1,
+            op if op == Op::EntryPoint as u32 => {
+                let (Some(&function), Some(name)) = (instruction.get(2), instruction.get(3..)) else {
+                    return Err(invalid("malformed OpEntryPoint".to_owned()));
+                };
+                entry_points.push((function, literal_string(name)));
+            }
+            op if op == Op::Function as u32 => {
+                if in_function {
+                    return Err(invalid("OpFunction inside a function".to_owned()));
+                }
+                in_function = true;
+                functions.extend(result.copied());
+            }
+            op if op == Op::FunctionEnd as u32 => {
+                if !in_function {
+                    return Err(invalid("OpFunctionEnd outside of a function".to_owned()));
+                }
+                in_function = false;
+            }
+            _ => {}
+        }
+    }
+
+    if in_function {
+        return Err(invalid("function without OpFunctionEnd".to_owned()));
+    }
+    if capabilities == 0 {
+        return Err(invalid("no OpCapability".to_owned()));
+    }
+    if memory_models != 1 {
+        return Err(invalid(format!("{memory_models} OpMemoryModel instructions instead of one")));
+    }
+    if entry_points.is_empty() {
+        return Err(invalid("no OpEntryPoint".to_owned()));
+    }
+    if let Some((_, name)) = entry_points.iter().find(|(function, _)| !functions.contains(function)) {
+        return Err(invalid(format!("entry point {name:?} does not name a function")));
+    }
+
+    Ok(())
+}
+
+/// Decodes a nul terminated UTF-8 literal packed into words.
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Copies the header and the instructions reflection reads. Function bodies,
+/// capabilities and decorations it ignores can use enumerants newer than the
+/// SPIR-V grammar of rspirv, e.g. those of `SPV_EXT_mesh_shader`. The EXT
+/// mesh and task execution models are rewritten to their NV counterparts.
+fn declarations(spirv: &[u8]) -> Result<Vec<u32>> {
+    const HEADER_WORDS: usize = 5;
+    const TASK_EXT: u32 = 5364;
+    const MESH_EXT: u32 = 5365;
+
+    let words = ash::util::read_spv(&mut Cursor::new(spirv))
+        .map_err(|error| Error::Reflection(format!("Invalid SPIR-V: {error}")))?;
+    if words.len() < HEADER_WORDS {
+        return Err(Error::Reflection("Invalid SPIR-V: truncated header".to_owned()));
+    }
+
+    // Resources and push constants live in these storage classes, and the
+    // buffer pointers inside them in the last one.
+    let storage_classes = [
+        StorageClass::PushConstant,
+        StorageClass::UniformConstant,
+        StorageClass::Uniform,
+        StorageClass::StorageBuffer,
+        StorageClass::PhysicalStorageBuffer,
+    ];
+    let kept_opcodes = [
+        Op::Name,
+        Op::MemberName,
+        Op::EntryPoint,
+        Op::TypeBool,
+        Op::TypeInt,
+        Op::TypeFloat,
+        Op::TypeVector,
+        Op::TypeMatrix,
+        Op::TypeImage,
+        Op::TypeSampler,
+        Op::TypeSampledImage,
+        Op::TypeArray,
+        Op::TypeRuntimeArray,
+        Op::TypeStruct,
+        Op::TypeAccelerationStructureKHR,
+        Op::Constant,
+    ];
+    let decorations = [
+        Decoration::DescriptorSet,
+        Decoration::Binding,
+        Decoration::ArrayStride,
+        Decoration::Block,
+        Decoration::BufferBlock,
+    ];
+    let storage_class = |word: Option<&u32>| {
+        word.is_some_and(|word| storage_classes.iter().any(|class| *class as u32 == *word))
+    };
+
+    let mut declarations = words[..HEADER_WORDS].to_vec();
+    let mut offset = HEADER_WORDS;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        let opcode = words[offset] & 0xffff;
+        let Some(instruction) = words.get(offset..offset + word_count).filter(|_| word_count > 0)
+        else {
+            return Err(Error::Reflection("Invalid SPIR-V: truncated instruction".to_owned()));
+        };
+        offset += word_count;
+
+        let keep = if kept_opcodes.iter().any(|op| *op as u32 == opcode) {
+            true
+        } else if opcode == Op::TypePointer as u32 {
+            storage_class(instruction.get(2))
+        } else if opcode == Op::Variable as u32 {
+            storage_class(instruction.get(3))
+        } else if opcode == Op::Decorate as u32 {
+            instruction
+                .get(2)
+                .is_some_and(|word| decorations.iter().any(|decoration| *decoration as u32 == *word))
+        } else if opcode == Op::MemberDecorate as u32 {
+            instruction.get(3) == Some(&(Decoration::Offset as u32))
+        } else {
+            false
+        };
+        if !keep {
+            continue;
+        }
+
+        let start = declarations.len();
+        declarations.extend_from_slice(instruction);
+        if opcode == Op::EntryPoint as u32 {
+            let model = &mut declarations[start + 1];
+            *model = match *model {
+                TASK_EXT => ExecutionModel::TaskNV as u32,
+                MESH_EXT => ExecutionModel::MeshNV as u32,
+                model => model,
+            };
+        }
+    }
+
+    Ok(declarations)
+}
+
+fn stage(model: ExecutionModel) -> Result<vk::ShaderStageFlags> {
+    Ok(match model {
+        ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+        ExecutionModel::TessellationControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        ExecutionModel::TessellationEvaluation => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        ExecutionModel::Geometry => vk::ShaderStageFlags::GEOMETRY,
+        ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        ExecutionModel::GLCompute => vk::ShaderStageFlags::COMPUTE,
+        ExecutionModel::TaskNV => vk::ShaderStageFlags::TASK_EXT,
+        ExecutionModel::MeshNV => vk::ShaderStageFlags::MESH_EXT,
+        model => {
+            return Err(Error::Reflection(format!(
+                "Unsupported execution model {model:?}"
+            )))
+        }
+    })
+}
+
+/// Lookup tables over the types, constants, decorations and names of a module.
+struct Types<'a> {
+    definitions: HashMap<u32, &'a Instruction>,
+    decorations: HashMap<(u32, Decoration), u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    blocks: HashMap<u32, Decoration>,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+}
+
+impl<'a> Types<'a> {
+    fn new(module: &'a Module) -> Types<'a> {
+        let definitions = module
+            .types_global_values
+            .iter()
+            .filter_map(|instruction| Some((instruction.result_id?, instruction)))
+            .collect();
+
+        let mut decorations = HashMap::new();
+        let mut member_offsets = HashMap::new();
+        let mut blocks = HashMap::new();
+        for annotation in &module.annotations {
+            match (annotation.class.opcode, annotation.operands.as_slice()) {
+                (Op::Decorate, [Operand::IdRef(id), Operand::Decoration(decoration)]) => {
+                    if matches!(decoration, Decoration::Block | Decoration::BufferBlock) {
+                        blocks.insert(*id, *decoration);
+                    }
+                }
+                (
+                    Op::Decorate,
+                    [Operand::IdRef(id), Operand::Decoration(decoration), Operand::LiteralInt32(value), ..],
+                ) => {
+                    decorations.insert((*id, *decoration), *value);
+                }
+                (
+                    Op::MemberDecorate,
+                    [Operand::IdRef(id), Operand::LiteralInt32(member), Operand::Decoration(Decoration::Offset), Operand::LiteralInt32(offset)],
+                ) => {
+                    member_offsets.insert((*id, *member), *offset);
+                }
+                _ => {}
+            }
+        }
+
+        let mut names = HashMap::new();
+        let mut member_names = HashMap::new();
+        for instruction in &module.debug_names {
+            match (instruction.class.opcode, instruction.operands.as_slice()) {
+                (Op::Name, [Operand::IdRef(id), Operand::LiteralString(name)]) => {
+                    names.insert(*id, name.clone());
+                }
+                (Op::MemberName, [Operand::IdRef(id), Operand::LiteralInt32(member), Operand::LiteralString(name)]) => {
+                    member_names.insert((*id, *member), name.clone());
+                }
+                _ => {}
+            }
+        }
+
+        Types {
+            definitions,
+            decorations,
+            member_offsets,
+            blocks,
+            names,
+            member_names,
+        }
+    }
+
+    fn decoration(&self, id: u32, decoration: Decoration) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn id_operand(&self, id: u32, index: usize) -> Option<u32> {
+        match self.definitions.get(&id)?.operands.get(index)? {
+            Operand::IdRef(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    fn literal_operand(&self, id: u32, index: usize) -> Option<u32> {
+        match self.definitions.get(&id)?.operands.get(index)? {
+            Operand::LiteralInt32(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn opcode(&self, id: u32) -> Option<Op> {
+        Some(self.definitions.get(&id)?.class.opcode)
+    }
+
+    fn pointee(&self, pointer: u32) -> Option<u32> {
+        self.id_operand(pointer, 1)
+    }
+
+    /// Strips one array level, returning the element type and the count.
+    fn array_element(&self, id: u32) -> (u32, u32) {
+        match self.opcode(id) {
+            Some(Op::TypeArray) => {
+                let element = self.id_operand(id, 0).unwrap_or(id);
+                let count = self
+                    .id_operand(id, 1)
+                    .and_then(|length| self.literal_operand(length, 0))
+                    .unwrap_or(1);
+                (element, count)
+            }
+            Some(Op::TypeRuntimeArray) => {
+                (self.id_operand(id, 0).unwrap_or(id), UNBOUNDED_DESCRIPTOR_COUNT)
+            }
+            _ => (id, 1),
+        }
+    }
+
+    fn descriptor_type(&self, storage_class: StorageClass, id: u32) -> Result<vk::DescriptorType> {
+        let unsupported = || {
+            Error::Reflection(format!(
+                "Unsupported resource type {:?} in {storage_class:?}",
+                self.opcode(id)
+            ))
+        };
+
+        Ok(match (storage_class, self.opcode(id)) {
+            (StorageClass::StorageBuffer, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (StorageClass::Uniform, _) => match self.blocks.get(&id) {
+                Some(Decoration::BufferBlock) => vk::DescriptorType::STORAGE_BUFFER,
+                _ => vk::DescriptorType::UNIFORM_BUFFER,
+            },
+            (_, Some(Op::TypeSampler)) => vk::DescriptorType::SAMPLER,
+            (_, Some(Op::TypeSampledImage)) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Some(Op::TypeAccelerationStructureKHR)) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            (_, Some(Op::TypeImage)) => {
+                let definition = self.definitions[&id];
+                let dim = match definition.operands.get(1) {
+                    Some(Operand::Dim(dim)) => *dim,
+                    _ => return Err(unsupported()),
+                };
+                // 1 means sampled, 2 means read and written without a sampler.
+                let sampled = self.literal_operand(id, 5).unwrap_or(1);
+                match (dim, sampled) {
+                    (Dim::DimBuffer, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (Dim::DimBuffer, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (Dim::DimSubpassData, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                    (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                    _ => vk::DescriptorType::SAMPLED_IMAGE,
+                }
+            }
+            _ => return Err(unsupported()),
+        })
+    }
+
+    /// Members of a struct type, none for other types.
+    fn members(&self, id: u32) -> Vec<Member> {
+        if self.opcode(id) != Some(Op::TypeStruct) {
+            return Vec::new();
+        }
+        (0..self.definitions[&id].operands.len() as u32)
+            .map(|member| Member {
+                name: self.member_names.get(&(id, member)).cloned(),
+                offset: self.member_offsets.get(&(id, member)).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
+    /// Size in bytes of a type as laid out in a block.
+    fn size(&self, id: u32) -> Result<u32> {
+        let unsupported = || Error::Reflection(format!("Cannot size type {:?}", self.opcode(id)));
+
+        Ok(match self.opcode(id) {
+            Some(Op::TypeInt | Op::TypeFloat) => {
+                self.literal_operand(id, 0).ok_or_else(unsupported)? / 8
+            }
+            Some(Op::TypeBool) => 4,
+            // Only physical storage buffer pointers can live in a block.
+            Some(Op::TypePointer) => 8,
+            Some(Op::TypeVector) => {
+                let component = self.id_operand(id, 0).ok_or_else(unsupported)?;
+                self.size(component)? * self.literal_operand(id, 1).ok_or_else(unsupported)?
+            }
+            Some(Op::TypeMatrix) => {
+                let column = self.id_operand(id, 0).ok_or_else(unsupported)?;
+                self.size(column)? * self.literal_operand(id, 1).ok_or_else(unsupported)?
+            }
+            Some(Op::TypeArray) => {
+                let (element, count) = self.array_element(id);
+                let stride = match self.decoration(id, Decoration::ArrayStride) {
+                    Some(stride) => stride,
+                    None => self.size(element)?,
+                };
+                stride * count
+            }
+            Some(Op::TypeStruct) => {
+                let members = self.definitions[&id].operands.len() as u32;
+                let mut size = 0;
+                for member in 0..members {
+                    let member_type = self.id_operand(id, member as usize).ok_or_else(unsupported)?;
+                    let offset = self.member_offsets.get(&(id, member)).copied().unwrap_or(size);
+                    size = size.max(offset + self.size(member_type)?);
+                }
+                size
+            }
+            _ => return Err(unsupported()),
+        })
+    }
+}
@@ -1,9 +1,48 @@
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::{
+    mem::ManuallyDrop,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH, Duration},
+};
 
 use ash::vk::{self, Buffer, BufferUsageFlags, CommandPoolResetFlags};
-use bytemuck::{Pod, Zeroable};
+use encase::ShaderType;
 use glam;
 use gpu_allocator::{vulkan::*, MemoryLocation};
+
+use crate::{
+    debug::DebugNames,
+    error::{Error, Result},
+    hot_reload::ShaderWatcher,
+    shader_cache::{self, ShaderBinaryCache},
+    shaders::{PermutationKey, ProgramCode, ProgramHandle, ShaderCode, Shaders, SHADER_DIR},
+    shared::{Globals, TrianglePushConstants},
+    typed_buffer::Layout,
+};
+
+/// Manifest program the renderer draws with. A mesh shader program, on devices
+/// without mesh shaders its fallback draws the same triangle with a vertex shader.
+pub const TRIANGLE_PROGRAM: &str = "triangle_mesh";
+
+/// Loaders of the optional device extensions that were enabled.
+#[derive(Clone, Default)]
+pub struct DeviceExtensions {
+    /// Without it shaders are built into graphics pipelines.
+    pub shader_object: Option<ash::extensions::ext::ShaderObject>,
+    /// Without it programs with mesh stages are replaced by their fallbacks.
+    pub mesh_shader: Option<ash::extensions::ext::MeshShader>,
+}
+
+/// The device a [`Renderer`] draws with and what it was created from.
+pub struct DeviceContext {
+    pub instance: ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: ash::Device,
+    pub extensions: DeviceExtensions,
+    pub queue: vk::Queue,
+    pub queue_family_index: u32,
+}
+
 pub struct BufferWithStaging {
     pub buffer: vk::Buffer,
     pub allocation: Allocation,
@@ -13,7 +52,14 @@ pub struct BufferWithStaging {
 }
 
 impl BufferWithStaging {
-    pub fn new(device_fn: &ash::Device, allocator: &mut Allocator) -> BufferWithStaging {
+    pub fn new(
+        device_fn: &ash::Device,
+        allocator: &mut Allocator,
+        debug: &DebugNames,
+        name: &str,
+    ) -> Result<BufferWithStaging> {
+        let staging_name = format!("{name} staging");
+
         unsafe {
             let buffer = device_fn
                 .create_buffer(
@@ -25,25 +71,24 @@ impl BufferWithStaging {
                         )
                         .build(),
                     None,
-                )
-                .expect("Could not create buffer.");
+                )?;
+
+            debug.name(buffer, name);
 
             let requirements = device_fn.get_buffer_memory_requirements(buffer);
 
             let allocation = allocator
                 .allocate(&AllocationCreateDesc {
-                    name: "Allocation",
+                    name,
                     requirements,
                     location: MemoryLocation::GpuOnly,
                     linear: true, // Buffers are always linear
                     allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-                })
-                .unwrap();
+                })?;
 
             // Bind memory to the buffer
             device_fn
-                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
-                .unwrap();
+                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?;
 
             let staging_buffer = device_fn
                 .create_buffer(
@@ -55,20 +100,20 @@ impl BufferWithStaging {
                         )
                         .build(),
                     None,
-                )
-                .expect("Could not create buffer.");
+                )?;
+
+            debug.name(staging_buffer, &staging_name);
 
             let requirements = device_fn.get_buffer_memory_requirements(buffer);
 
             let staging_allocation = allocator
                 .allocate(&AllocationCreateDesc {
-                    name: "Allocation",
+                    name: &staging_name,
                     requirements,
                     location: MemoryLocation::CpuToGpu,
                     linear: true, // Buffers are always linear
                     allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-                })
-                .unwrap();
+                })?;
 
             // Bind memory to the buffer
             device_fn
@@ -76,18 +121,87 @@ impl BufferWithStaging {
                     staging_buffer,
                     staging_allocation.memory(),
                     staging_allocation.offset(),
-                )
-                .unwrap();
+                )?;
 
-            BufferWithStaging {
+            Ok(BufferWithStaging {
                 buffer,
                 allocation,
 
                 staging_buffer,
                 staging_allocation,
-            }
+            })
         }
     }
+
+    pub fn destroy(&mut self, device_fn: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device_fn.destroy_buffer(self.buffer, None);
+            device_fn.destroy_buffer(self.staging_buffer, None);
+        }
+        allocator.free(std::mem::take(&mut self.allocation)).unwrap();
+        allocator.free(std::mem::take(&mut self.staging_allocation)).unwrap();
+    }
+}
+
+/// Buffer created through [`Renderer::create_buffer`]. Shaders reach it through
+/// its device address.
+///
+/// It dies with the device. After a device loss [`Renderer::owns`] is false
+/// for it and [`Renderer::destroy_buffer`] only forgets it, data that has to
+/// survive belongs in a persistent buffer.
+pub struct GpuBuffer {
+    pub buffer: vk::Buffer,
+    pub allocation: Allocation,
+    pub size: vk::DeviceSize,
+    pub address: vk::DeviceAddress,
+    /// Of the renderer that created it.
+    generation: u64,
+}
+
+/// Refers to a buffer of [`Renderer::create_persistent_buffer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+/// A buffer that is recreated on a new device, with what the host last wrote
+/// to it.
+struct PersistentBuffer {
+    buffer: GpuBuffer,
+    usage: vk::BufferUsageFlags,
+    location: MemoryLocation,
+    name: String,
+    contents: Vec<u8>,
+}
+
+impl PersistentBuffer {
+    fn upload(&mut self) {
+        self.buffer.allocation.mapped_slice_mut().expect("Host visible memory is mapped")[..self.contents.len()]
+            .copy_from_slice(&self.contents);
+    }
+}
+
+/// Tells apart the renderers, and with them the devices, buffers come from.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Where a dispatch takes its workgroup counts from.
+#[derive(Clone, Copy, Debug)]
+pub enum DispatchSize {
+    Groups([u32; 3]),
+    /// A `VkDispatchIndirectCommand` at `offset` in `buffer`, e.g. written by
+    /// an earlier dispatch. The buffer needs `INDIRECT_BUFFER` usage.
+    Indirect {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+    },
+}
+
+/// A compute dispatch, see [`Renderer::queue_dispatch`].
+#[derive(Clone, Debug)]
+pub struct Dispatch {
+    pub program: ProgramHandle,
+    /// Usually device addresses of [`GpuBuffer`]s. Has to match the
+    /// program's push constant range.
+    pub push_constants: Vec<u8>,
+    pub size: DispatchSize,
 }
 
 pub struct PerFrameData {
@@ -104,8 +218,10 @@ impl PerFrameData {
     pub fn new(
         device_fn: &ash::Device,
         allocator: &mut Allocator,
+        debug: &DebugNames,
         queue_family_index: u32,
-    ) -> PerFrameData {
+        index: usize,
+    ) -> Result<PerFrameData> {
         let command_pool_create_info = vk::CommandPoolCreateInfo {
             flags: vk::CommandPoolCreateFlags::empty(),
             queue_family_index,
@@ -114,20 +230,17 @@ impl PerFrameData {
 
         let command_pool = unsafe {
             device_fn
-                .create_command_pool(&command_pool_create_info, None)
-                .expect("Could not create command pool.")
+                .create_command_pool(&command_pool_create_info, None)?
         };
 
         let image_available_semaphore = unsafe {
             device_fn
-                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                .expect("")
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
         };
 
         let render_finished_semaphore = unsafe {
             device_fn
-                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                .expect("")
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
         };
 
         let in_flight_fence = unsafe {
@@ -138,13 +251,18 @@ impl PerFrameData {
                         ..Default::default()
                     },
                     None,
-                )
-                .expect("")
+                )?
         };
 
-        let test_buffer = BufferWithStaging::new(device_fn, allocator);
+        debug.name(command_pool, &format!("Frame {index} command pool"));
+        debug.name(image_available_semaphore, &format!("Frame {index} image available"));
+        debug.name(render_finished_semaphore, &format!("Frame {index} render finished"));
+        debug.name(in_flight_fence, &format!("Frame {index} in flight"));
 
-        PerFrameData {
+        let test_buffer =
+            BufferWithStaging::new(device_fn, allocator, debug, &format!("Frame {index} globals"))?;
+
+        Ok(PerFrameData {
             command_pool,
 
             image_available_semaphore,
@@ -152,25 +270,65 @@ impl PerFrameData {
             in_flight_fence,
 
             test_buffer,
+        })
+    }
+
+    pub fn destroy(&mut self, device_fn: &ash::Device, allocator: &mut Allocator) {
+        unsafe {
+            device_fn.destroy_command_pool(self.command_pool, None);
+            device_fn.destroy_semaphore(self.image_available_semaphore, None);
+            device_fn.destroy_semaphore(self.render_finished_semaphore, None);
+            device_fn.destroy_fence(self.in_flight_fence, None);
         }
+        self.test_buffer.destroy(device_fn, allocator);
     }
 }
 
-#[derive(Copy, Clone, Pod, Zeroable)]
-#[repr(C)]
-struct Globals {
-    pub transform: glam::Mat4,
+/// What a frame is drawn into: the window's swapchain, or an offscreen color
+/// image when running headless.
+pub enum RenderTarget {
+    // Boxed, it is several times larger than the offscreen image.
+    Swapchain(Box<crate::swapchain::Swapchain>),
+    Offscreen(crate::offscreen::Offscreen),
+    /// Destroyed with the device or surface it belonged to and not created
+    /// again yet. Nothing is left to destroy.
+    Lost,
 }
+
+/// Source of the time that animates each frame.
+#[derive(Copy, Clone, Debug)]
+pub enum Clock {
+    /// Wall-clock time since the renderer was created.
+    System,
+    /// Deterministic time: frame `n` sees `start + n * step`. Used by tests that
+    /// need the same image on every run.
+    Fixed { start: Duration, step: Duration },
+}
+
 pub struct Renderer {
     instance: ash::Instance,
     device: ash::Device,
     physical_device: vk::PhysicalDevice,
     queue: vk::Queue,
-
-    allocator: Allocator,
-
-    shader_object_loader: ash::extensions::ext::ShaderObject,
-    shaders: crate::shaders::Shaders,
+    queue_family_index: u32,
+
+    // Dropped in `destroy`, its memory blocks have to be freed before the device.
+    allocator: ManuallyDrop<Allocator>,
+
+    shaders: Shaders,
+    triangle: ProgramHandle,
+    extensions: DeviceExtensions,
+    /// Only used with shader objects.
+    shader_binary_cache: Option<ShaderBinaryCache>,
+    shader_watcher: Option<ShaderWatcher>,
+    /// Replaced shaders and the frame index they were replaced at. Destroyed
+    /// once no frame in flight can still use them.
+    retired_shaders: Vec<(u64, Shaders)>,
+    /// Recorded before the draws of the next frame.
+    dispatches: Vec<Dispatch>,
+    debug: DebugNames,
+    generation: u64,
+    persistent_buffers: Vec<Option<PersistentBuffer>>,
 
     frames_in_flight: usize,
     current_frame: usize,
@@ -178,19 +336,34 @@ pub struct Renderer {
     per_frame_data: Vec<PerFrameData>,
 
     start_time: Duration,
+    clock: Clock,
+    frame_index: u64,
 }
 
 impl Renderer {
-    pub fn new(
-        instance: ash::Instance,
-        physical_device: vk::PhysicalDevice,
-        device: ash::Device,
-        shader_object_loader: ash::extensions::ext::ShaderObject,
-        queue: vk::Queue,
-        queue_family_index: u32,
-        frames_in_flight: usize,
-    ) -> Renderer {
-        let shaders = crate::shaders::Shaders::new(&device, &shader_object_loader);
+    pub fn new(context: DeviceContext, debug: DebugNames, frames_in_flight: usize) -> Result<Renderer> {
+        let DeviceContext {
+            instance,
+            physical_device,
+            device,
+            extensions,
+            queue,
+            queue_family_index,
+        } = context;
+        let shader_binary_cache = extensions.shader_object.as_ref().map(|_| {
+            ShaderBinaryCache::new(
+                &instance,
+                physical_device,
+                &shader_cache::directory_from_env(),
+            )
+        });
+        let shaders = Shaders::new(
+            &device,
+            &extensions,
+            shader_binary_cache.as_ref(),
+            &debug,
+        )?;
+        let triangle = shaders.require(TRIANGLE_PROGRAM)?;
 
         let mut allocator = Allocator::new(&AllocatorCreateDesc {
             instance: instance.clone(),
@@ -198,16 +371,17 @@ impl Renderer {
             physical_device,
             debug_settings: Default::default(),
             buffer_device_address: true, // Ideally, check the BufferDeviceAddressFeatures struct.
-        })
-        .expect("Could not create allocator");
+        })?;
 
         let mut per_frame_data = Vec::new();
         for i in 0..frames_in_flight {
             per_frame_data.push(PerFrameData::new(
                 &device,
                 &mut allocator,
+                &debug,
                 queue_family_index,
-            ));
+                i,
+            )?);
         }
 
         let start = SystemTime::now();
@@ -215,50 +389,633 @@ impl Renderer {
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
 
-        Renderer {
+        Ok(Renderer {
             instance,
             physical_device,
             device,
             queue,
+            queue_family_index,
 
-            allocator,
+            allocator: ManuallyDrop::new(allocator),
 
-            shader_object_loader,
             shaders,
+            triangle,
+            extensions,
+            shader_binary_cache,
+            shader_watcher: None,
+            retired_shaders: Vec::new(),
+            dispatches: Vec::new(),
+            debug,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+            persistent_buffers: Vec::new(),
 
             frames_in_flight,
             current_frame: 0,
 
             per_frame_data,
 
-            start_time
+            start_time,
+            clock: Clock::System,
+            frame_index: 0,
+        })
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.frame_index = 0;
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Recompiled shaders from `watcher` replace the current ones at the start of
+    /// a frame. `None` stops reloading.
+    pub fn set_shader_watcher(&mut self, watcher: Option<ShaderWatcher>) {
+        self.shader_watcher = watcher;
+    }
+
+    pub fn take_shader_watcher(&mut self) -> Option<ShaderWatcher> {
+        self.shader_watcher.take()
+    }
+
+    /// Swaps in newly compiled shaders and destroys replaced ones that are no
+    /// longer in use. Called after waiting for the current frame's fence, at
+    /// which point every frame up to `frame_index - frames_in_flight` is done.
+    fn reload_shaders(&mut self) {
+        let frame_index = self.frame_index;
+        let frames_in_flight = self.frames_in_flight as u64;
+        self.retired_shaders.retain_mut(|(retired_at, shaders)| {
+            // The last frame that used these is `retired_at - 1`.
+            let unused = frame_index + 1 >= *retired_at + frames_in_flight;
+            if unused {
+                shaders.destroy();
+            }
+            !unused
+        });
+
+        let Some(code) = self.shader_watcher.as_ref().and_then(ShaderWatcher::poll) else {
+            return;
+        };
+        let reloaded = Shaders::from_code(
+            &self.device,
+            &self.extensions,
+            self.shader_binary_cache.as_ref(),
+            &self.debug,
+            &code,
+        )
+        .and_then(|mut shaders| {
+            match shaders.recreate_variants(&self.shaders, self.shader_binary_cache.as_ref()) {
+                Ok(()) => Ok(shaders),
+                Err(error) => {
+                    shaders.destroy();
+                    Err(error)
+                }
+            }
+        });
+        match reloaded {
+            Ok(mut shaders) => {
+                // Handles given out earlier have to keep pointing at the same programs.
+                if shaders.keeps_handles_of(&self.shaders) {
+                    let old = std::mem::replace(&mut self.shaders, shaders);
+                    self.retired_shaders.push((self.frame_index, old));
+                } else {
+                    shaders.destroy();
+                    log::error!("Keeping previous shaders. Programs were removed or reordered, restart to apply.");
+                }
+            }
+            Err(error) => log::error!("Keeping previous shaders. {error}"),
         }
     }
 
-    pub fn destroy(&mut self) {
+    /// Programs of the shader manifest, to look up handles in.
+    pub fn shaders(&self) -> &Shaders {
+        &self.shaders
+    }
+
+    /// Handle of a variant of `program`, see [`Shaders::permutation`].
+    pub fn permutation(&mut self, program: ProgramHandle, key: &PermutationKey) -> Result<ProgramHandle> {
+        self.shaders
+            .permutation(program, key, self.shader_binary_cache.as_ref())
+    }
+
+    /// Records `dispatch` into the next frame, before anything is drawn.
+    /// Dispatches run in the order they were queued and each one sees the
+    /// writes of the ones before it. The frame's draws see all of them,
+    /// including indirect arguments. They stay queued until a frame is
+    /// submitted, so a frame that fails to record does not lose them.
+    pub fn queue_dispatch(&mut self, dispatch: Dispatch) {
+        self.dispatches.push(dispatch);
+    }
+
+    /// Creates a buffer that shaders can address. `SHADER_DEVICE_ADDRESS` is
+    /// added to `usage`.
+    pub fn create_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+        name: &str,
+    ) -> Result<GpuBuffer> {
         unsafe {
-            self.device.device_wait_idle().unwrap();
+            let buffer = self.device.create_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+                None,
+            )?;
+            self.debug.name(buffer, name);
+
+            let requirements = self.device.get_buffer_memory_requirements(buffer);
+            let allocation = match self.allocator.allocate(&AllocationCreateDesc {
+                name,
+                requirements,
+                location,
+                linear: true, // Buffers are always linear
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            }) {
+                Ok(allocation) => allocation,
+                Err(error) => {
+                    self.device.destroy_buffer(buffer, None);
+                    return Err(error.into());
+                }
+            };
+            if let Err(error) = self.device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset()) {
+                self.device.destroy_buffer(buffer, None);
+                if let Err(error) = self.allocator.free(allocation) {
+                    log::error!("Could not free buffer memory: {error}");
+                }
+                return Err(error.into());
+            }
 
-            // self.device.destroy_command_pool(self.command_pool, None);
+            Ok(GpuBuffer {
+                buffer,
+                allocation,
+                size,
+                address: self.buffer_address(buffer),
+                generation: self.generation,
+            })
+        }
+    }
+
+    /// The buffer must not be used by a frame that is still in flight. Buffers
+    /// of a lost device are only forgotten, their memory went with the device.
+    pub fn destroy_buffer(&mut self, buffer: &mut GpuBuffer) {
+        if !self.owns(buffer) {
+            log::warn!("Forgetting buffer {:?} of a lost device", buffer.buffer);
+            buffer.buffer = vk::Buffer::null();
+            buffer.allocation = Allocation::default();
+            return;
+        }
+        unsafe { self.device.destroy_buffer(buffer.buffer, None) };
+        buffer.buffer = vk::Buffer::null();
+        if let Err(error) = self.allocator.free(std::mem::take(&mut buffer.allocation)) {
+            log::error!("Could not free buffer memory: {error}");
+        }
+    }
+
+    /// Whether `buffer` was created on this renderer's device, which is no
+    /// longer the case after the device was lost and replaced.
+    pub fn owns(&self, buffer: &GpuBuffer) -> bool {
+        buffer.generation == self.generation
+    }
+
+    /// Creates a host visible buffer holding `contents` that survives device
+    /// loss. [`crate::app::App`] recreates it on the new device with what was
+    /// last written through [`Renderer::write_buffer`], at a new address.
+    /// Writes of shaders are lost. `location` has to be host visible.
+    pub fn create_persistent_buffer(
+        &mut self,
+        contents: &[u8],
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+        name: &str,
+    ) -> Result<BufferHandle> {
+        if location == MemoryLocation::GpuOnly {
+            return Err(Error::NotHostVisible { name: name.to_owned() });
+        }
+        let buffer = self.create_buffer(contents.len() as u64, usage, location, name)?;
+        let mut persistent = PersistentBuffer {
+            buffer,
+            usage,
+            location,
+            name: name.to_owned(),
+            contents: contents.to_vec(),
+        };
+        persistent.upload();
+
+        let handle = match self.persistent_buffers.iter().position(Option::is_none) {
+            Some(index) => BufferHandle(index),
+            None => {
+                self.persistent_buffers.push(None);
+                BufferHandle(self.persistent_buffers.len() - 1)
+            }
         };
+        self.persistent_buffers[handle.0] = Some(persistent);
+        Ok(handle)
+    }
+
+    /// Panics for handles of destroyed buffers.
+    pub fn persistent_buffer(&self, handle: BufferHandle) -> &GpuBuffer {
+        &self.persistent(handle).buffer
+    }
+
+    fn persistent(&self, handle: BufferHandle) -> &PersistentBuffer {
+        self.persistent_buffers[handle.0]
+            .as_ref()
+            .expect("Persistent buffer was destroyed")
+    }
+
+    /// Writes `bytes` at `offset` into the buffer and into the copy it is
+    /// restored from. Panics if they do not fit.
+    pub fn write_buffer(&mut self, handle: BufferHandle, offset: usize, bytes: &[u8]) {
+        let persistent = self.persistent_buffers[handle.0]
+            .as_mut()
+            .expect("Persistent buffer was destroyed");
+        persistent.contents[offset..offset + bytes.len()].copy_from_slice(bytes);
+        persistent.buffer.allocation.mapped_slice_mut().expect("Host visible memory is mapped")
+            [offset..offset + bytes.len()]
+            .copy_from_slice(bytes);
+    }
 
-        // unsafe {
-        //     for semaphore in self.image_available_semaphores.iter_mut() {
-        //         self.device.destroy_semaphore(*semaphore, None);
-        //     }
+    /// The buffer must not be used by a frame that is still in flight.
+    pub fn destroy_persistent_buffer(&mut self, handle: BufferHandle) {
+        if let Some(mut persistent) = self.persistent_buffers[handle.0].take() {
+            self.destroy_buffer(&mut persistent.buffer);
+        }
+    }
 
-        //     for semaphore in self.render_finished_semaphores.iter_mut() {
-        //         self.device.destroy_semaphore(*semaphore, None);
-        //     }
+    /// Takes over the persistent buffers of `lost`, the renderer of a lost
+    /// device, under the same handles. Each one is created again on this
+    /// renderer's device and its contents uploaded. If one cannot be created,
+    /// `lost` keeps all of them and can be restored from again.
+    pub fn restore_persistent_buffers(&mut self, lost: &mut Renderer) -> Result<()> {
+        let mut buffers = Vec::new();
+        for (index, persistent) in lost.persistent_buffers.iter().enumerate() {
+            let Some(persistent) = persistent else {
+                continue;
+            };
+            let buffer = self.create_buffer(
+                persistent.contents.len() as u64,
+                persistent.usage,
+                persistent.location,
+                &persistent.name,
+            );
+            match buffer {
+                Ok(buffer) => buffers.push((index, buffer)),
+                Err(error) => {
+                    for (_, mut buffer) in buffers {
+                        self.destroy_buffer(&mut buffer);
+                    }
+                    return Err(error);
+                }
+            }
+        }
 
-        //     for fence in self.in_flight_fences.iter_mut() {
-        //         self.device.destroy_fence(*fence, None);
-        //     }
-        // }
+        if self.persistent_buffers.len() < lost.persistent_buffers.len() {
+            self.persistent_buffers.resize_with(lost.persistent_buffers.len(), || None);
+        }
+        for (index, buffer) in buffers {
+            let mut persistent = lost.persistent_buffers[index].take().expect("Restored buffers exist");
+            lost.destroy_buffer(&mut persistent.buffer);
+            persistent.buffer = buffer;
+            persistent.upload();
+            self.persistent_buffers[index] = Some(persistent);
+        }
+        lost.persistent_buffers.clear();
+        Ok(())
     }
 
-    pub fn render(&mut self, swapchain: &mut crate::swapchain::Swapchain) -> bool {
+    pub fn buffer_address(&self, buffer: vk::Buffer) -> vk::DeviceAddress {
         unsafe {
+            self.device
+                .get_buffer_device_address(&vk::BufferDeviceAddressInfo::builder().buffer(buffer))
+        }
+    }
+
+    /// Records the queued dispatches, each followed by a barrier that makes its
+    /// writes visible to the next dispatch and to the draws after it.
+    unsafe fn record_dispatches(
+        device: &ash::Device,
+        shaders: &mut Shaders,
+        debug: &DebugNames,
+        dispatches: &[Dispatch],
+        command_buffer: vk::CommandBuffer,
+    ) -> Result<()> {
+        if dispatches.is_empty() {
+            return Ok(());
+        }
+        let _compute = debug.label(command_buffer, "Compute");
+
+        for dispatch in dispatches {
+            let program = shaders.program(dispatch.program);
+            let _label = debug.label(command_buffer, &program.name);
+            let (layout, stages) = (program.pipeline_layout, program.layout.push_constant_stages());
+
+            shaders.bind_compute(command_buffer, dispatch.program)?;
+            if !dispatch.push_constants.is_empty() {
+                device.cmd_push_constants(command_buffer, layout, stages, 0, &dispatch.push_constants);
+            }
+            match dispatch.size {
+                DispatchSize::Groups([x, y, z]) => device.cmd_dispatch(command_buffer, x, y, z),
+                DispatchSize::Indirect { buffer, offset } => {
+                    device.cmd_dispatch_indirect(command_buffer, buffer, offset)
+                }
+            }
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::DRAW_INDIRECT
+                    | vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(
+                        vk::AccessFlags::SHADER_READ
+                            | vk::AccessFlags::SHADER_WRITE
+                            | vk::AccessFlags::INDIRECT_COMMAND_READ
+                            | vk::AccessFlags::TRANSFER_READ,
+                    )
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Names objects and labels command buffers of this renderer's device.
+    pub fn debug_names(&self) -> &DebugNames {
+        &self.debug
+    }
+
+    fn frame_time(&self) -> Duration {
+        match self.clock {
+            Clock::System => {
+                let start = SystemTime::now();
+                let since_the_epoch = start
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards");
+                since_the_epoch - self.start_time
+            }
+            Clock::Fixed { start, step } => start + step * self.frame_index as u32,
+        }
+    }
+
+    pub fn create_offscreen(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<crate::offscreen::Offscreen> {
+        crate::offscreen::Offscreen::new(
+            &self.device,
+            &mut self.allocator,
+            &self.debug,
+            width,
+            height,
+        )
+    }
+
+    pub fn destroy_offscreen(&mut self, offscreen: &mut crate::offscreen::Offscreen) {
+        offscreen.destroy(&self.device, &mut self.allocator);
+    }
+
+    /// Copies the offscreen image back to the host as tightly packed RGBA8 rows.
+    /// Waits for all submitted frames, so only use it outside the frame loop.
+    pub fn read_offscreen(&mut self, offscreen: &crate::offscreen::Offscreen) -> Result<Vec<u8>> {
+        let size = (offscreen.width * offscreen.height * 4) as vk::DeviceSize;
+
+        unsafe {
+            self.device.device_wait_idle()?;
+
+            let mut buffer = self.create_buffer(
+                size,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                MemoryLocation::GpuToCpu,
+                "Readback",
+            )?;
+            let command_pool = match self.device.create_command_pool(
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                    queue_family_index: self.queue_family_index,
+                    ..Default::default()
+                },
+                None,
+            ) {
+                Ok(command_pool) => command_pool,
+                Err(error) => {
+                    self.destroy_buffer(&mut buffer);
+                    return Err(error.into());
+                }
+            };
+            let fence = match self.device.create_fence(&vk::FenceCreateInfo::default(), None) {
+                Ok(fence) => fence,
+                Err(error) => {
+                    self.device.destroy_command_pool(command_pool, None);
+                    self.destroy_buffer(&mut buffer);
+                    return Err(error.into());
+                }
+            };
+
+            let result = (|| -> Result<Vec<u8>> {
+                let command_buffer = self.device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_pool,
+                    command_buffer_count: 1,
+                    ..Default::default()
+                })?[0];
+                self.device.begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )?;
+
+                self.debug.begin_label(command_buffer, "Readback");
+
+                // The render loop leaves offscreen images in TRANSFER_SRC_OPTIMAL.
+                self.device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    offscreen.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    buffer.buffer,
+                    &[vk::BufferImageCopy {
+                        buffer_offset: 0,
+                        buffer_row_length: 0,
+                        buffer_image_height: 0,
+                        image_subresource: vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+                        image_offset: vk::Offset3D::default(),
+                        image_extent: vk::Extent3D {
+                            width: offscreen.width,
+                            height: offscreen.height,
+                            depth: 1,
+                        },
+                    }],
+                );
+
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[vk::MemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::HOST_READ)
+                        .build()],
+                    &[],
+                    &[],
+                );
+
+                self.debug.end_label(command_buffer);
+                self.device.end_command_buffer(command_buffer)?;
+
+                self.device.queue_submit(
+                    self.queue,
+                    &[vk::SubmitInfo::builder().command_buffers(&[command_buffer]).build()],
+                    fence,
+                )?;
+                self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+                Ok(buffer
+                    .allocation
+                    .mapped_slice()
+                    .expect("Could not map memory")[..size as usize]
+                    .to_vec())
+            })();
+
+            self.device.destroy_fence(fence, None);
+            self.device.destroy_command_pool(command_pool, None);
+            self.destroy_buffer(&mut buffer);
+            result
+        }
+    }
+
+    /// Creates the compute program of `code` on its own, dispatches it once and
+    /// waits for it, so its writes can be read on the host. The program is not
+    /// registered with [`Renderer::shaders`]. Waits for all submitted frames,
+    /// so only use it outside the frame loop.
+    pub fn dispatch_once(&mut self, code: ProgramCode, push_constants: Vec<u8>, groups: [u32; 3]) -> Result<()> {
+        let name = code.desc.name.clone();
+        let mut shaders = Shaders::from_code(
+            &self.device,
+            &self.extensions,
+            None,
+            &self.debug,
+            &ShaderCode {
+                programs: vec![code],
+                directory: PathBuf::from(SHADER_DIR),
+                include_paths: Vec::new(),
+            },
+        )?;
+        let result = shaders.require(&name).and_then(|program| unsafe {
+            self.device.device_wait_idle()?;
+
+            let command_pool = self.device.create_command_pool(
+                &vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                    queue_family_index: self.queue_family_index,
+                    ..Default::default()
+                },
+                None,
+            )?;
+            let command_buffer = self.device.allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                level: vk::CommandBufferLevel::PRIMARY,
+                command_pool,
+                command_buffer_count: 1,
+                ..Default::default()
+            })?[0];
+            let fence = self.device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+            let result = (|| -> Result<()> {
+                self.device.begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )?;
+                Self::record_dispatches(
+                    &self.device,
+                    &mut shaders,
+                    &self.debug,
+                    &[Dispatch {
+                        program,
+                        push_constants,
+                        size: DispatchSize::Groups(groups),
+                    }],
+                    command_buffer,
+                )?;
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[vk::MemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::HOST_READ)
+                        .build()],
+                    &[],
+                    &[],
+                );
+                self.device.end_command_buffer(command_buffer)?;
+
+                self.device.queue_submit(
+                    self.queue,
+                    &[vk::SubmitInfo::builder().command_buffers(&[command_buffer]).build()],
+                    fence,
+                )?;
+                self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+                Ok(())
+            })();
+
+            self.device.destroy_fence(fence, None);
+            self.device.destroy_command_pool(command_pool, None);
+            result
+        });
+        shaders.destroy();
+        result
+    }
+
+    /// Destroys everything the renderer created on its device. Has to be called
+    /// exactly once, before the device itself is destroyed.
+    pub fn destroy(&mut self) {
+        unsafe {
+            // A lost device cannot be waited on, its work is gone anyway.
+            let _ = self.device.device_wait_idle();
+
+            self.shaders.destroy();
+            for (_, shaders) in self.retired_shaders.iter_mut() {
+                shaders.destroy();
+            }
+            self.retired_shaders.clear();
+
+            for frame_data in self.per_frame_data.iter_mut() {
+                frame_data.destroy(&self.device, &mut self.allocator);
+            }
+            self.per_frame_data.clear();
+
+            for mut persistent in std::mem::take(&mut self.persistent_buffers).into_iter().flatten() {
+                self.destroy_buffer(&mut persistent.buffer);
+            }
+
+            ManuallyDrop::drop(&mut self.allocator);
+        };
+    }
+
+    /// Draws one frame into `target`.
+    ///
+    /// Returns [`Error::SwapchainOutOfDate`] when the swapchain has to be
+    /// recreated before the next frame, which is also the case when it was
+    /// presented to but no longer matches the surface exactly, and
+    /// [`Error::SurfaceLost`] for a [`RenderTarget::Lost`] target.
+    pub fn render(&mut self, target: &mut RenderTarget) -> Result<()> {
+        unsafe {
+            let t = self.frame_time();
             let frame_data = &mut self.per_frame_data[self.current_frame];
 
             let in_flight_fence = frame_data.in_flight_fence;
@@ -267,18 +1024,13 @@ impl Renderer {
 
             // Wait for previous work
             self.device
-                .wait_for_fences(&[in_flight_fence], true, std::u64::MAX)
-                .expect("");
+                .wait_for_fences(&[in_flight_fence], true, std::u64::MAX)?;
 
-            self.device.reset_fences(&[in_flight_fence]).expect("");
+            self.reload_shaders();
+            let frame_data = &mut self.per_frame_data[self.current_frame];
 
             // Copy over data
-            let start = SystemTime::now();
-            let since_the_epoch = start
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards");
-            let t = since_the_epoch - self.start_time;
-            println!("{:?}", t.as_secs_f64().sin() as f32);
+            log::trace!("{:?}", t.as_secs_f64().sin() as f32);
 
             let globals = Globals {
                 transform: glam::Mat4::from_rotation_z(t.as_secs_f64().sin() as f32 + 1.0f32),
@@ -289,37 +1041,27 @@ impl Renderer {
             let staging_map = buffer
                 .staging_allocation
                 .mapped_slice_mut()
-                .expect("Coult not map memory");
-
-            {
-                let (left, right) = staging_map.split_at_mut(64);
+                .expect("Could not map memory");
 
-                left.copy_from_slice(bytemuck::bytes_of::<Globals>(&globals));
-            }
-
-            // Aquire Image
-            let acquire_next_image = swapchain.loader.acquire_next_image(
-                swapchain.inner,
-                std::u64::MAX,
-                image_available_semaphore,
-                vk::Fence::null(),
-            );
+            Layout::Storage.write(&globals, staging_map)?;
+            let (globals_buffer, globals_staging_buffer) = (buffer.buffer, buffer.staging_buffer);
 
-            let swapchain_image_index = match acquire_next_image {
-                Ok((image_index, _)) => image_index,
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    return true;
-                }
-                Err(error) => panic!("Error while acquiring next image. Cause: {}", error),
+            let (format, width, height) = match target {
+                RenderTarget::Swapchain(swapchain) => (swapchain.format, swapchain.width, swapchain.height),
+                RenderTarget::Offscreen(offscreen) => (offscreen.format, offscreen.width, offscreen.height),
+                RenderTarget::Lost => return Err(Error::SurfaceLost),
             };
 
+            // Everything that can fail while recording happens before the image is
+            // acquired, and the fence is only reset right before the submit. An
+            // early return then leaves the fence signaled and no image acquired.
+
             // Reset command pool
             self.device
                 .reset_command_pool(
                     frame_data.command_pool,
                     CommandPoolResetFlags::RELEASE_RESOURCES,
-                )
-                .expect("");
+                )?;
 
             let allocate_command_buffer_create_info = vk::CommandBufferAllocateInfo {
                 level: vk::CommandBufferLevel::PRIMARY,
@@ -330,23 +1072,105 @@ impl Renderer {
 
             let command_buffer = self
                 .device
-                .allocate_command_buffers(&allocate_command_buffer_create_info)
-                .expect("Could not allocate command buffers.")[0];
+                .allocate_command_buffers(&allocate_command_buffer_create_info)?[0];
 
             // Record command buffers
-            let shaders = &self.shaders.shaders;
             let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder().build();
 
             self.device
-                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
-                .expect("Could not start command buffer recoring.");
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
+            let frame_label = self
+                .debug
+                .label(command_buffer, &format!("Frame {}", self.frame_index));
 
             // HERE GO RENDER COMMANDS
+            // Copy staing buffer to actual buffer
+            {
+                let _upload = self.debug.label(command_buffer, "Upload globals");
+                self.device.cmd_copy_buffer2(
+                    command_buffer,
+                    &vk::CopyBufferInfo2::builder()
+                        .src_buffer(globals_staging_buffer)
+                        .dst_buffer(globals_buffer)
+                        .regions(&[vk::BufferCopy2::builder().size(globals.size().get()).build()]),
+                );
+            }
+
+            // The globals are read by every stage. Work of earlier frames, which
+            // may still read what this frame's dispatches write, has to finish.
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER
+                    | vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build()],
+                &[],
+                &[],
+            );
+
+            Self::record_dispatches(
+                &self.device,
+                &mut self.shaders,
+                &self.debug,
+                &self.dispatches,
+                command_buffer,
+            )?;
+
+            // Bound state carries over into the rendering below.
+            let triangle_label = self.debug.label(command_buffer, "Triangle");
+            self.shaders.bind(command_buffer, self.triangle, format)?;
+            self.shaders.set_dynamic_state(command_buffer, width, height);
+
+            let device_address = self.buffer_address(globals_buffer);
+            let triangle = self.shaders.program(self.triangle);
+            self.device.cmd_push_constants(command_buffer, triangle.pipeline_layout, triangle.layout.push_constant_stages(), 0, bytemuck::bytes_of(&TrianglePushConstants { globals_address: device_address }));
+
+            // Aquire Image
+            let (image, image_view, swapchain_image_index) = match target {
+                RenderTarget::Swapchain(swapchain) => {
+                    let acquire_next_image = swapchain.loader.acquire_next_image(
+                        swapchain.inner,
+                        std::u64::MAX,
+                        image_available_semaphore,
+                        vk::Fence::null(),
+                    );
+
+                    let (swapchain_image_index, _) = acquire_next_image?;
+
+                    (
+                        swapchain.present_images[swapchain_image_index as usize],
+                        swapchain.present_image_views[swapchain_image_index as usize],
+                        Some(swapchain_image_index),
+                    )
+                }
+                RenderTarget::Offscreen(offscreen) => (offscreen.image, offscreen.view, None),
+                RenderTarget::Lost => return Err(Error::SurfaceLost),
+            };
+
+            // An offscreen image is reused by every frame, so the previous frame's
+            // color writes and readback copies have to finish before we clear it.
+            let (src_stage, src_access) = match swapchain_image_index {
+                Some(_) => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+                None => (
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ),
+            };
+
             let image_memory_barrier = vk::ImageMemoryBarrier::builder()
+                .src_access_mask(src_access)
                 .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
                 .old_layout(vk::ImageLayout::UNDEFINED)
                 .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .image(swapchain.present_images[swapchain_image_index as usize])
+                .image(image)
                 .subresource_range(vk::ImageSubresourceRange {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     base_mip_level: 0,
@@ -358,7 +1182,7 @@ impl Renderer {
 
             self.device.cmd_pipeline_barrier(
                 command_buffer,
-                vk::PipelineStageFlags::TOP_OF_PIPE,
+                src_stage,
                 vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
                 vk::DependencyFlags::empty(),
                 &[],
@@ -366,17 +1190,8 @@ impl Renderer {
                 &[image_memory_barrier],
             );
 
-            // Copy staing buffer to actual buffer
-            self.device.cmd_copy_buffer2(
-                command_buffer,
-                &vk::CopyBufferInfo2::builder()
-                    .src_buffer(buffer.staging_buffer)
-                    .dst_buffer(buffer.buffer)
-                    .regions(&[vk::BufferCopy2::builder().size(64).build()]),
-            );
-
             let rendering_attachment_infos = vec![vk::RenderingAttachmentInfo::builder()
-                .image_view(swapchain.present_image_views[swapchain_image_index as usize])
+                .image_view(image_view)
                 .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
                 .load_op(vk::AttachmentLoadOp::CLEAR)
                 .store_op(vk::AttachmentStoreOp::STORE)
@@ -390,10 +1205,7 @@ impl Renderer {
             let rendering_info = vk::RenderingInfo::builder()
                 .render_area(vk::Rect2D {
                     offset: vk::Offset2D::default(),
-                    extent: vk::Extent2D {
-                        width: swapchain.width,
-                        height: swapchain.height,
-                    },
+                    extent: vk::Extent2D { width, height },
                 })
                 .layer_count(1)
                 .color_attachments(&rendering_attachment_infos)
@@ -402,43 +1214,37 @@ impl Renderer {
             self.device
                 .cmd_begin_rendering(command_buffer, &rendering_info);
 
-            self.device.cmd_set_viewport_with_count(
-                command_buffer,
-                &[vk::Viewport {
-                    width: swapchain.width as f32,
-                    height: swapchain.height as f32,
-                    ..Default::default()
-                }],
-            );
-            self.device.cmd_set_scissor_with_count(
-                command_buffer,
-                &[vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: vk::Extent2D {
-                        width: swapchain.width as u32,
-                        height: swapchain.height as u32,
-                    },
-                }],
-            );
-            self.shader_object_loader.cmd_bind_shaders(
-                command_buffer,
-                &[vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT],
-                &[shaders[0], shaders[1]],
-            );
-            self.shader_object_loader
-                .cmd_set_primitive_topology(command_buffer, vk::PrimitiveTopology::TRIANGLE_LIST);
-
-            let device_address = self.device.get_buffer_device_address(&vk::BufferDeviceAddressInfo::builder().buffer(buffer.buffer));
-            self.device.cmd_push_constants(command_buffer, self.shaders.layouts[0], vk::ShaderStageFlags::VERTEX, 0, bytemuck::bytes_of::<u64>(&device_address));
-            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            match &self.extensions.mesh_shader {
+                Some(mesh_shader) if triangle.has_mesh_stages() => {
+                    mesh_shader.cmd_draw_mesh_tasks(command_buffer, 1, 1, 1)
+                }
+                _ => self.device.cmd_draw(command_buffer, 3, 1, 0, 0),
+            }
 
             self.device.cmd_end_rendering(command_buffer);
+            drop(triangle_label);
+
+            // Swapchain images go to the presentation engine, offscreen images are
+            // left ready to be copied out.
+            let (dst_stage, dst_access, final_layout) = match swapchain_image_index {
+                Some(_) => (
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::AccessFlags::empty(),
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                ),
+                None => (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_READ,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ),
+            };
 
             let image_memory_barrier = vk::ImageMemoryBarrier::builder()
                 .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(dst_access)
                 .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                .image(swapchain.present_images[swapchain_image_index as usize])
+                .new_layout(final_layout)
+                .image(image)
                 .subresource_range(vk::ImageSubresourceRange {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     base_mip_level: 0,
@@ -451,49 +1257,65 @@ impl Renderer {
             self.device.cmd_pipeline_barrier(
                 command_buffer,
                 vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                dst_stage,
                 vk::DependencyFlags::empty(),
                 &[],
                 &[],
                 &[image_memory_barrier],
             );
 
+            drop(frame_label);
             self.device
-                .end_command_buffer(command_buffer)
-                .expect("Could not end command buffer recording.");
-
-            // Submit
-            let queue_submits = vec![vk::SubmitInfo::builder()
-                .command_buffers(&[command_buffer])
-                .wait_semaphores(&[image_available_semaphore])
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .signal_semaphores(&[render_finished_semaphore])
-                .build()];
-
-            self.device
-                .queue_submit(self.queue, &queue_submits, in_flight_fence)
-                .expect("");
-
-            // Present
-            let present_info = vk::PresentInfoKHR::builder()
-                .wait_semaphores(&[render_finished_semaphore])
-                .swapchains(&[swapchain.inner])
-                .image_indices(&[swapchain_image_index])
-                .build();
+                .end_command_buffer(command_buffer)?;
+
+            let suboptimal = match (target, swapchain_image_index) {
+                (RenderTarget::Swapchain(swapchain), Some(swapchain_image_index)) => {
+                    // Submit
+                    let queue_submits = vec![vk::SubmitInfo::builder()
+                        .command_buffers(&[command_buffer])
+                        .wait_semaphores(&[image_available_semaphore])
+                        .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+                        .signal_semaphores(&[render_finished_semaphore])
+                        .build()];
+
+                    self.device.reset_fences(&[in_flight_fence])?;
+                    self.device
+                        .queue_submit(self.queue, &queue_submits, in_flight_fence)?;
+                    // Kept until now so a frame that fails earlier runs them next time.
+                    self.dispatches.clear();
+
+                    // Present
+                    let present_info = vk::PresentInfoKHR::builder()
+                        .wait_semaphores(&[render_finished_semaphore])
+                        .swapchains(&[swapchain.inner])
+                        .image_indices(&[swapchain_image_index])
+                        .build();
+
+                    swapchain.loader.queue_present(self.queue, &present_info)?
+                }
+                _ => {
+                    // Submit
+                    let queue_submits = vec![vk::SubmitInfo::builder()
+                        .command_buffers(&[command_buffer])
+                        .build()];
+
+                    self.device.reset_fences(&[in_flight_fence])?;
+                    self.device
+                        .queue_submit(self.queue, &queue_submits, in_flight_fence)?;
+                    self.dispatches.clear();
+
+                    false
+                }
+            };
 
-            let present_result = swapchain.loader.queue_present(self.queue, &present_info);
+            self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
+            self.frame_index += 1;
 
-            match present_result {
-                Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    return true;
-                }
-                Err(error) => panic!("Failed to present queue. Cause: {}", error),
-                _ => {}
+            if suboptimal {
+                return Err(Error::SwapchainOutOfDate);
             }
         };
 
-        self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
-
-        return false;
+        Ok(())
     }
 }
@@ -1,53 +1,461 @@
-use std::ffi::{CString, CStr, c_char};
+use std::{
+    cmp::Reverse,
+    ffi::{CStr, CString, c_char},
+    fmt::Write,
+};
 
 use ash::vk::{self, SurfaceKHR, MAX_EXTENSION_NAME_SIZE, MAX_PHYSICAL_DEVICE_NAME_SIZE};
 
-pub unsafe fn is_device_suitable(
+use crate::error::{Error, Result};
+
+/// Environment variable that forces a physical device, see [`DeviceOverride`].
+pub const DEVICE_ENV: &str = "VULKAN_DEVICE";
+
+/// Picks a physical device by its enumeration index or by a case-insensitive
+/// substring of its name, instead of the best scoring one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceOverride {
+    Index(usize),
+    Name(String),
+}
+
+impl DeviceOverride {
+    pub fn parse(value: &str) -> DeviceOverride {
+        match value.parse::<usize>() {
+            Ok(index) => DeviceOverride::Index(index),
+            Err(_) => DeviceOverride::Name(value.to_owned()),
+        }
+    }
+
+    pub fn from_env() -> Option<DeviceOverride> {
+        std::env::var(DEVICE_ENV)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(|value| DeviceOverride::parse(&value))
+    }
+
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            DeviceOverride::Index(i) => *i == index,
+            DeviceOverride::Name(n) => name.to_lowercase().contains(&n.to_lowercase()),
+        }
+    }
+}
+
+/// Device feature bit the renderer can require or take advantage of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    ShaderObject,
+    DynamicRendering,
+    BufferDeviceAddress,
+    /// Both `meshShader` and `taskShader` of `VK_EXT_mesh_shader`.
+    MeshShader,
+}
+
+impl Feature {
+    /// Name of the member in the Vulkan feature struct.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::ShaderObject => "shaderObject",
+            Feature::DynamicRendering => "dynamicRendering",
+            Feature::BufferDeviceAddress => "bufferDeviceAddress",
+            Feature::MeshShader => "meshShader",
+        }
+    }
+
+    /// Extension that exposes the feature struct on devices older than `core_version`.
+    pub fn extension(self) -> &'static CStr {
+        match self {
+            Feature::ShaderObject => ash::extensions::ext::ShaderObject::name(),
+            Feature::DynamicRendering => ash::extensions::khr::DynamicRendering::name(),
+            Feature::BufferDeviceAddress => ash::extensions::khr::BufferDeviceAddress::name(),
+            Feature::MeshShader => ash::extensions::ext::MeshShader::name(),
+        }
+    }
+
+    pub fn core_version(self) -> Option<u32> {
+        match self {
+            Feature::ShaderObject | Feature::MeshShader => None,
+            Feature::DynamicRendering => Some(vk::API_VERSION_1_3),
+            Feature::BufferDeviceAddress => Some(vk::API_VERSION_1_2),
+        }
+    }
+}
+
+/// Extensions and feature bits a device must, or may, support to be used.
+///
+/// Required entries make a device unsuitable when missing, optional ones are
+/// enabled when present and only count towards the device score.
+#[derive(Clone, Debug)]
+pub struct DeviceRequirements {
+    pub required_extensions: Vec<&'static CStr>,
+    pub optional_extensions: Vec<&'static CStr>,
+    pub required_features: Vec<Feature>,
+    pub optional_features: Vec<Feature>,
+    /// Vulkan version devices without shader objects need. The graphics
+    /// pipelines they fall back to leave the extended dynamic state of
+    /// Vulkan 1.3 dynamic, which the renderer sets through the core commands.
+    pub pipeline_api_version: u32,
+}
+
+impl DeviceRequirements {
+    /// What the renderer needs. Presenting additionally needs `VK_KHR_swapchain`.
+    pub fn new(presents: bool) -> DeviceRequirements {
+        let mut required_extensions = vec![
+            ash::extensions::khr::DynamicRendering::name(),
+            ash::extensions::khr::BufferDeviceAddress::name(),
+        ];
+        if presents {
+            required_extensions.push(ash::extensions::khr::Swapchain::name());
+        }
+
+        DeviceRequirements {
+            required_extensions,
+            // Shaders fall back to graphics pipelines without shader objects,
+            // and mesh shader programs to their vertex shader fallbacks.
+            optional_extensions: vec![
+                ash::extensions::ext::ShaderObject::name(),
+                ash::extensions::ext::MeshShader::name(),
+            ],
+            required_features: vec![Feature::DynamicRendering, Feature::BufferDeviceAddress],
+            optional_features: vec![Feature::ShaderObject, Feature::MeshShader],
+            pipeline_api_version: vk::API_VERSION_1_3,
+        }
+    }
+}
+
+/// Result of checking one physical device against [`DeviceRequirements`].
+#[derive(Clone, Debug)]
+pub struct DeviceReport {
+    pub index: usize,
+    pub name: String,
+    pub physical_device: vk::PhysicalDevice,
+
+    /// Graphics queue family, able to present when a surface was given.
+    pub queue_family_index: Option<u32>,
+
+    pub missing_extensions: Vec<&'static CStr>,
+    pub missing_features: Vec<Feature>,
+    pub missing_optional_extensions: Vec<&'static CStr>,
+    pub missing_optional_features: Vec<Feature>,
+    /// [`DeviceRequirements::pipeline_api_version`] if the device lacks shader
+    /// objects and is older.
+    pub missing_api_version: Option<u32>,
+
+    /// Everything from the requirements the device supports, to be enabled at
+    /// device creation.
+    pub enabled_extensions: Vec<&'static CStr>,
+    pub enabled_features: Vec<Feature>,
+
+    /// Queries that failed while checking the device, which make it unsuitable.
+    pub errors: Vec<String>,
+}
+
+impl DeviceReport {
+    pub fn is_suitable(&self) -> bool {
+        self.queue_family_index.is_some()
+            && self.missing_extensions.is_empty()
+            && self.missing_features.is_empty()
+            && self.missing_api_version.is_none()
+            && self.errors.is_empty()
+    }
+
+    pub fn has_feature(&self, feature: Feature) -> bool {
+        self.enabled_features.contains(&feature)
+    }
+
+    /// Human readable list of what is missing, empty for suitable devices.
+    pub fn missing(&self) -> String {
+        let mut missing = Vec::new();
+        if self.queue_family_index.is_none() {
+            missing.push("no graphics queue family that can present".to_owned());
+        }
+        if !self.missing_extensions.is_empty() {
+            missing.push(format!("missing extensions {:?}", self.missing_extensions));
+        }
+        if !self.missing_features.is_empty() {
+            let names = self.missing_features.iter().map(|f| f.name()).collect::<Vec<_>>();
+            missing.push(format!("missing features {:?}", names));
+        }
+        if let Some(version) = self.missing_api_version {
+            missing.push(format!(
+                "no shader objects and older than Vulkan {}.{}",
+                vk::api_version_major(version),
+                vk::api_version_minor(version)
+            ));
+        }
+        missing.extend(self.errors.iter().cloned());
+        missing.join(", ")
+    }
+}
+
+/// Ranking of a suitable device. Fields are compared in order, so the device
+/// type always wins over feature coverage, which wins over memory size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DeviceScore {
+    pub device_type: u32,
+    pub feature_coverage: u32,
+    pub vram: u64,
+}
+
+unsafe fn device_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    CStr::from_bytes_until_nul(&std::mem::transmute::<[c_char; MAX_PHYSICAL_DEVICE_NAME_SIZE], [u8; MAX_PHYSICAL_DEVICE_NAME_SIZE ]>(properties.device_name)).unwrap().to_string_lossy().into_owned()
+}
+
+unsafe fn available_extensions(
     instance: &ash::Instance,
-    surface_loader: &ash::extensions::khr::Surface,
-    surface: &SurfaceKHR,
     physical_device: vk::PhysicalDevice,
-) -> Option<usize> {
-    let mut properties = vk::PhysicalDeviceProperties2::default();
-    instance.get_physical_device_properties2(physical_device, &mut properties);
+) -> ash::prelude::VkResult<Vec<CString>> {
+    let available_extensions = instance.enumerate_device_extension_properties(physical_device)?;
+    Ok(available_extensions.into_iter().map(|e| {
+        CStr::from_bytes_until_nul(&std::mem::transmute::<[c_char; MAX_EXTENSION_NAME_SIZE], [u8; MAX_EXTENSION_NAME_SIZE]>(e.extension_name)).unwrap().to_owned()
+    }).collect::<Vec<_>>())
+}
 
-    let mut features = vk::PhysicalDeviceFeatures2::default();
-    instance.get_physical_device_features2(physical_device, &mut features);
+/// Feature bits the device reports as supported, out of `features`.
+///
+/// A feature struct is only chained when the device exposes it, either through
+/// its API version or through the extension that introduced it.
+unsafe fn supported_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+    available_extensions: &[CString],
+    features: &[Feature],
+) -> Vec<Feature> {
+    let exposed = |feature: Feature| {
+        feature.core_version().is_some_and(|version| api_version >= version)
+            || available_extensions.iter().any(|e| e.as_c_str() == feature.extension())
+    };
 
-    let available_extensions = instance.enumerate_device_extension_properties(physical_device).expect("Could not iterate device extensions");
-    let available_extensions = available_extensions.into_iter().map(|e| unsafe {
-        CStr::from_bytes_until_nul(&std::mem::transmute::<[c_char; MAX_EXTENSION_NAME_SIZE], [u8; MAX_EXTENSION_NAME_SIZE]>(e.extension_name)).unwrap().to_owned()
-    }).collect::<Vec<_>>();
+    let mut shader_object = vk::PhysicalDeviceShaderObjectFeaturesEXT::default();
+    let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+    let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder();
+    if exposed(Feature::ShaderObject) {
+        features2 = features2.push_next(&mut shader_object);
+    }
+    if exposed(Feature::DynamicRendering) {
+        features2 = features2.push_next(&mut dynamic_rendering);
+    }
+    if exposed(Feature::BufferDeviceAddress) {
+        features2 = features2.push_next(&mut buffer_device_address);
+    }
+    if exposed(Feature::MeshShader) {
+        features2 = features2.push_next(&mut mesh_shader);
+    }
+    let mut features2 = features2.build();
+    instance.get_physical_device_features2(physical_device, &mut features2);
+
+    features
+        .iter()
+        .copied()
+        .filter(|feature| match feature {
+            Feature::ShaderObject => shader_object.shader_object == vk::TRUE,
+            Feature::DynamicRendering => dynamic_rendering.dynamic_rendering == vk::TRUE,
+            Feature::BufferDeviceAddress => buffer_device_address.buffer_device_address == vk::TRUE,
+            Feature::MeshShader => {
+                mesh_shader.mesh_shader == vk::TRUE && mesh_shader.task_shader == vk::TRUE
+            }
+        })
+        .collect()
+}
+
+pub fn score_device(
+    instance: &ash::Instance,
+    report: &DeviceReport,
+) -> DeviceScore {
+    let properties = unsafe { instance.get_physical_device_properties(report.physical_device) };
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(report.physical_device) };
+
+    let device_type = match properties.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    };
 
-    let device_name = CStr::from_bytes_until_nul(&std::mem::transmute::<[c_char; MAX_PHYSICAL_DEVICE_NAME_SIZE], [u8; MAX_PHYSICAL_DEVICE_NAME_SIZE ]>(properties.properties.device_name)).unwrap().to_owned();
-    println!("{:?} {:?}", device_name, ash::extensions::ext::ShaderObject::name());
-    println!("{:?}", available_extensions);
-    println!("{:?}", available_extensions.contains(&ash::extensions::ext::ShaderObject::name().to_owned()));
+    let feature_coverage = (report.enabled_extensions.len() + report.enabled_features.len()) as u32;
 
-    let contains_dynamic_rendering = available_extensions.contains(&ash::extensions::khr::DynamicRendering::name().to_owned());
-    let contains_shader_object = available_extensions.contains(&ash::extensions::ext::ShaderObject::name().to_owned());
+    let vram = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+
+    DeviceScore {
+        device_type,
+        feature_coverage,
+        vram,
+    }
+}
+
+/// Checks every extension and feature of `requirements` and finds a graphics
+/// queue family. Reports what is missing instead of stopping at the first gap.
+///
+/// # Safety
+///
+/// `physical_device` has to come from `instance`, and `surface`, if given,
+/// has to be a live surface of it.
+pub unsafe fn is_device_suitable(
+    instance: &ash::Instance,
+    surface: Option<(&ash::extensions::khr::Surface, &SurfaceKHR)>,
+    requirements: &DeviceRequirements,
+    index: usize,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceReport {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let mut errors = Vec::new();
+    // Without the list every extension counts as missing.
+    let available_extensions = available_extensions(instance, physical_device).unwrap_or_else(|error| {
+        errors.push(format!("could not enumerate extensions ({error})"));
+        Vec::new()
+    });
 
-    // if !contains_shader_object || !contains_dynamic_rendering {
-    //     return None;
-    // }
+    let is_available = |name: &&'static CStr| available_extensions.iter().any(|e| e.as_c_str() == *name);
+    let (enabled_required_extensions, missing_extensions): (Vec<_>, Vec<_>) =
+        requirements.required_extensions.iter().copied().partition(is_available);
+    let (enabled_optional_extensions, missing_optional_extensions): (Vec<_>, Vec<_>) =
+        requirements.optional_extensions.iter().copied().partition(is_available);
 
+    let all_features = [
+        requirements.required_features.as_slice(),
+        requirements.optional_features.as_slice(),
+    ]
+    .concat();
+    let supported = supported_features(
+        instance,
+        physical_device,
+        properties.api_version,
+        &available_extensions,
+        &all_features,
+    );
+
+    let (enabled_required_features, missing_features): (Vec<_>, Vec<_>) =
+        requirements.required_features.iter().copied().partition(|f| supported.contains(f));
+    let (enabled_optional_features, missing_optional_features): (Vec<_>, Vec<_>) =
+        requirements.optional_features.iter().copied().partition(|f| supported.contains(f));
+
+    // Without shader objects every program becomes a graphics pipeline.
+    let missing_api_version = (!supported.contains(&Feature::ShaderObject)
+        && properties.api_version < requirements.pipeline_api_version)
+        .then_some(requirements.pipeline_api_version);
+
+    // A family whose surface support cannot be queried is skipped, the errors
+    // only matter when no other family fits.
+    let mut surface_errors = Vec::new();
     let queue_family_index = instance
         .get_physical_device_queue_family_properties(physical_device)
         .iter()
         .enumerate()
         .find_map(|(index, info)| {
+            // Headless devices only need to draw, there is nothing to present to.
             let supports_graphic_and_surface = info
                 .queue_flags
                 .contains(vk::QueueFlags::GRAPHICS)
-                && surface_loader
-                    .get_physical_device_surface_support(physical_device, index as u32, *surface)
-                    .unwrap();
+                && surface.is_none_or(|(surface_loader, surface)| {
+                    surface_loader
+                        .get_physical_device_surface_support(physical_device, index as u32, *surface)
+                        .unwrap_or_else(|error| {
+                            surface_errors
+                                .push(format!("could not query surface support of queue family {index} ({error})"));
+                            false
+                        })
+                });
             if supports_graphic_and_surface {
-                Some(index)
+                Some(index as u32)
             } else {
                 None
             }
         });
+    if queue_family_index.is_none() {
+        errors.extend(surface_errors);
+    }
+
+    DeviceReport {
+        index,
+        name: device_name(&properties),
+        physical_device,
+
+        queue_family_index,
+
+        missing_extensions,
+        missing_features,
+        missing_optional_extensions,
+        missing_optional_features,
+        missing_api_version,
+
+        enabled_extensions: [enabled_required_extensions, enabled_optional_extensions].concat(),
+        enabled_features: [enabled_required_features, enabled_optional_features].concat(),
+
+        errors,
+    }
+}
+
+/// Picks the physical device to render with.
+///
+/// Without an override the highest scoring suitable device wins. With one, the
+/// matching device is used, and it is an error if it does not exist or is not
+/// suitable. Every device gets a log line with the reason it was picked or not,
+/// and the error lists what each device is missing.
+///
+/// # Safety
+///
+/// `surface`, if given, has to be a live surface of `instance`.
+pub unsafe fn select_physical_device(
+    instance: &ash::Instance,
+    surface: Option<(&ash::extensions::khr::Surface, &SurfaceKHR)>,
+    requirements: &DeviceRequirements,
+    device_override: Option<&DeviceOverride>,
+) -> Result<DeviceReport> {
+    let physical_devices = instance.enumerate_physical_devices()?;
+
+    let mut candidates = Vec::new();
+    let mut rejections = String::new();
+    for (index, &physical_device) in physical_devices.iter().enumerate() {
+        let report = is_device_suitable(instance, surface, requirements, index, physical_device);
+
+        if let Some(device_override) = device_override {
+            if !device_override.matches(index, &report.name) {
+                log::info!("Device {index} {:?}: rejected, does not match override {device_override:?}", report.name);
+                continue;
+            }
+        }
+
+        if report.is_suitable() {
+            let score = score_device(instance, &report);
+            candidates.push((score, report));
+        } else {
+            log::info!("Device {index} {:?}: rejected, {}", report.name, report.missing());
+            writeln!(rejections, "  Device {index} {:?}: {}", report.name, report.missing()).unwrap();
+        }
+    }
+
+    // Stable sort, so equally scored devices keep their enumeration order.
+    candidates.sort_by_key(|(score, _)| Reverse(*score));
+
+    for (rank, (score, report)) in candidates.iter().enumerate() {
+        if rank == 0 {
+            log::info!("Device {} {:?}: accepted, {score:?}", report.index, report.name);
+        } else {
+            log::info!("Device {} {:?}: rejected, ranked lower ({score:?})", report.index, report.name);
+        }
+        if !report.missing_optional_extensions.is_empty() || !report.missing_optional_features.is_empty() {
+            log::info!(
+                "Device {} {:?}: without optional extensions {:?} and features {:?}",
+                report.index, report.name, report.missing_optional_extensions, report.missing_optional_features
+            );
+        }
+    }
 
-    return queue_family_index;
+    match candidates.into_iter().next() {
+        Some((_, report)) => Ok(report),
+        None => match device_override {
+            Some(device_override) => Err(Error::MissingFeatures(format!("No suitable physical device matches override {:?}\n{}", device_override, rejections))),
+            None => Err(Error::MissingFeatures(rejections)),
+        },
+    }
 }
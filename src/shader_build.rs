@@ -0,0 +1,239 @@
+//! Compiles every variant of every program in a shader manifest ahead of time,
+//! so shader breakage fails a build step instead of the app's startup.
+//!
+//! ```text
+//! shader_build [--manifest shaders/shaders.toml] [--out target/shaders] [--cache DIR]
+//! ```
+//!
+//! The SPIR-V goes through the same front ends and settings as at runtime and
+//! lands in the shader cache, so the app then runs without DXC. Cache entries
+//! are keyed by paths relative to the manifest's directory, so any spelling of
+//! `--manifest` fills the entries the app looks up. For inspection, each
+//! variant's stages are also written to `--out` as `<variant>.<stage>.spv`
+//! next to `<variant>.json` with their reflection; the app never reads them. Errors are printed as
+//! `file:line: error: ...` and make the exit code non-zero.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{bail, Context};
+use serde_json::json;
+use vulkan_modern::{
+    error::{Error, Result},
+    manifest::{Manifest, ProgramDesc, MANIFEST_FILE},
+    reflection,
+    shader_cache::{self, ShaderCache},
+    shaders::{PermutationKey, ProgramCode, SHADER_DIR},
+};
+
+struct Options {
+    manifest: PathBuf,
+    out: PathBuf,
+    cache: PathBuf,
+}
+
+impl Options {
+    fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Options> {
+        let mut options = Options {
+            manifest: Path::new(SHADER_DIR).join(MANIFEST_FILE),
+            out: PathBuf::from("target/shaders"),
+            cache: shader_cache::directory_from_env(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), value.to_owned()),
+                None => {
+                    let value = args.next().with_context(|| format!("{arg} expects a path"))?;
+                    (arg, value)
+                }
+            };
+            match flag.as_str() {
+                "--manifest" => options.manifest = value.into(),
+                "--out" => options.out = value.into(),
+                "--cache" => options.cache = value.into(),
+                _ => bail!("Unknown argument {flag}, expected --manifest, --out or --cache"),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {error:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let text = fs::read_to_string(&options.manifest).unwrap_or_default();
+    let manifest = match Manifest::load(&options.manifest) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            let line = match &error {
+                Error::Manifest { message, .. } => toml_line(message).or_else(|| {
+                    let name = message.strip_prefix("Program \"")?.split('"').next()?;
+                    program_line(&text, name)
+                }),
+                _ => None,
+            };
+            eprintln!("{}: error: {error}", location(&options.manifest, line));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let directory = options.manifest.parent().unwrap_or(Path::new(""));
+    let cache = ShaderCache::new(&options.cache);
+    if !cache.compiler_available() {
+        eprintln!("warning: DXC is not available, HLSL stages are only taken from the cache");
+    }
+    if let Err(source) = fs::create_dir_all(&options.out) {
+        eprintln!("{}: error: {source}", options.out.display());
+        return ExitCode::FAILURE;
+    }
+
+    let mut built = 0;
+    let mut failed = 0;
+    for program in &manifest.programs {
+        for key in PermutationKey::all(program) {
+            let variant = variant_name(program, &key);
+            let result = ProgramCode::compile(directory, &manifest.include_paths, program, &key, &cache)
+                .and_then(|code| write_artifacts(&code, &variant, &options.out));
+            match result {
+                Ok(()) => built += 1,
+                Err(Error::ShaderCompilation {
+                    path,
+                    entry_point,
+                    message,
+                }) => {
+                    failed += 1;
+                    eprintln!(
+                        "{}: error: could not compile {entry_point} of {variant}\n{message}",
+                        path.display()
+                    );
+                }
+                Err(error) => {
+                    failed += 1;
+                    let line = program_line(&text, &program.name);
+                    eprintln!("{}: error: {variant}: {error}", location(&options.manifest, line));
+                }
+            }
+        }
+    }
+
+    println!(
+        "Built {built} variants of {} programs into {}, {failed} failed",
+        manifest.programs.len(),
+        options.out.display()
+    );
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Validates and reflects `code` like the runtime does, then writes its SPIR-V
+/// and reflection.
+fn write_artifacts(code: &ProgramCode, variant: &str, out: &Path) -> Result<()> {
+    for stage in &code.stages {
+        reflection::validate(&stage.spirv).map_err(|error| match error {
+            Error::Reflection(message) => Error::Reflection(format!("{:?} stage: {message}", stage.stage)),
+            error => error,
+        })?;
+    }
+    let (reflections, layout) = code.reflect()?;
+
+    let write = |path: PathBuf, contents: &[u8]| {
+        fs::write(&path, contents).map_err(|source| Error::Io { path, source })
+    };
+    let mut stages = Vec::new();
+    for ((stage, desc), reflection) in code.stages.iter().zip(&code.desc.stages).zip(&reflections) {
+        let stage_name = format!("{:?}", stage.stage).to_lowercase();
+        write(out.join(format!("{variant}.{stage_name}.spv")), &stage.spirv)?;
+
+        let bindings: Vec<_> = reflection
+            .bindings
+            .iter()
+            .map(|binding| {
+                json!({
+                    "set": binding.set,
+                    "binding": binding.binding,
+                    "descriptor_type": format!("{:?}", binding.descriptor_type),
+                    "count": binding.count,
+                    "name": binding.name,
+                })
+            })
+            .collect();
+        stages.push(json!({
+            "stage": stage_name,
+            "source": desc.source,
+            "entry_point": reflection.entry_points[0].name,
+            "push_constant_size": reflection.push_constant_size,
+            "bindings": bindings,
+        }));
+    }
+
+    let push_constant_ranges: Vec<_> = layout
+        .push_constant_ranges
+        .iter()
+        .map(|range| {
+            json!({
+                "stages": format!("{:?}", range.stage_flags),
+                "offset": range.offset,
+                "size": range.size,
+            })
+        })
+        .collect();
+    let json = json!({
+        "program": code.desc.name,
+        "defines": code.key.defines,
+        "stages": stages,
+        "push_constant_ranges": push_constant_ranges,
+    });
+    let json = serde_json::to_string_pretty(&json).expect("JSON values always serialize");
+    write(out.join(format!("{variant}.json")), json.as_bytes())
+}
+
+/// `fill` for the default variant, `fill.OFFSET=1` for others.
+fn variant_name(desc: &ProgramDesc, key: &PermutationKey) -> String {
+    key.defines
+        .iter()
+        .fold(desc.name.clone(), |name, (define, value)| format!("{name}.{define}={value}"))
+}
+
+fn location(path: &Path, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{line}", path.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Line of a TOML syntax error, from `TOML parse error at line 3, column 1`.
+fn toml_line(message: &str) -> Option<usize> {
+    let (_, rest) = message.split_once("at line ")?;
+    rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+}
+
+/// Line of the `name` of the `[[program]]` table declaring `name`.
+fn program_line(text: &str, name: &str) -> Option<usize> {
+    let mut in_program = false;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_program = line == "[[program]]";
+        } else if let Some((key, value)) = line.split_once('=') {
+            if in_program && key.trim() == "name" && value.trim() == format!("{name:?}") {
+                return Some(index + 1);
+            }
+        }
+    }
+    None
+}
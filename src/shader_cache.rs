@@ -0,0 +1,365 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ash::vk;
+use com_rs::ComPtr;
+use hassle_rs::{
+    Dxc, DxcCreateInstanceProc, DxcIncludeHandler, HassleError, IDxcVersionInfo, IDxcVersionInfo2, CLSID_DxcCompiler,
+    IID_IDxcVersionInfo,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+/// Environment variable overriding [`DEFAULT_DIR`].
+pub const CACHE_DIR_ENV: &str = "SHADER_CACHE_DIR";
+pub const DEFAULT_DIR: &str = "target/shader-cache";
+
+// Where hassle-rs loads DXC from.
+#[cfg(target_os = "windows")]
+const DXC_LIBRARY: &str = "dxcompiler.dll";
+#[cfg(target_os = "macos")]
+const DXC_LIBRARY: &str = "./libdxcompiler.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const DXC_LIBRARY: &str = "./libdxcompiler.so";
+
+/// Everything that determines the SPIR-V of one entry point.
+#[derive(Clone, Copy, Debug)]
+pub struct CompileRequest<'a> {
+    /// Directory of the shader manifest. `path`, arguments and includes under
+    /// it enter the cache relative to it, so entries are found however it is
+    /// spelled, e.g. after `shader_build --manifest` with an absolute path.
+    pub directory: &'a str,
+    pub path: &'a str,
+    pub source: &'a str,
+    pub entry_point: &'a str,
+    pub profile: &'a str,
+    pub args: &'a [&'a str],
+    pub defines: &'a [(&'a str, Option<&'a str>)],
+}
+
+impl CompileRequest<'_> {
+    /// Hash of all inputs known before compiling. Includes are only known
+    /// afterwards, so they are checked against the entry's metadata instead.
+    pub fn key(&self) -> String {
+        let mut hasher = Sha256::new();
+        let mut field = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+
+        field(self.relative(self.path).as_bytes());
+        field(self.source.as_bytes());
+        field(self.entry_point.as_bytes());
+        field(self.profile.as_bytes());
+        for arg in self.args {
+            field(self.relative(arg).as_bytes());
+        }
+        for (name, value) in self.defines {
+            field(name.as_bytes());
+            field(value.unwrap_or("1").as_bytes());
+        }
+
+        hex(&hasher.finalize())
+    }
+
+    /// `path` relative to [`CompileRequest::directory`] if it is inside it.
+    fn relative(&self, path: &str) -> String {
+        match Path::new(path).strip_prefix(self.directory) {
+            Ok(relative) if !self.directory.is_empty() => relative.to_string_lossy().into_owned(),
+            _ => path.to_owned(),
+        }
+    }
+}
+
+/// SPIR-V of one entry point and every file it included.
+pub struct CompileOutput {
+    pub spirv: Vec<u8>,
+    /// As DXC resolved them, relative to the working directory unless an
+    /// include directory was absolute.
+    pub includes: Vec<PathBuf>,
+}
+
+/// Persistent cache of compiled SPIR-V.
+///
+/// An entry is `<key>.spv` next to `<key>.meta`, which records the compiler
+/// version and a hash of every included file. Entries are only used when the
+/// includes are unchanged and, if DXC is installed, it is the same version.
+/// Without DXC any entry with matching inputs is used, so the renderer can run
+/// purely from a populated cache.
+pub struct ShaderCache {
+    pub directory: PathBuf,
+    /// `None` when DXC cannot be loaded.
+    pub compiler_version: Option<String>,
+}
+
+impl ShaderCache {
+    pub fn new(directory: impl Into<PathBuf>) -> ShaderCache {
+        ShaderCache {
+            directory: directory.into(),
+            compiler_version: compiler_version(),
+        }
+    }
+
+    pub fn from_env() -> ShaderCache {
+        ShaderCache::new(directory_from_env())
+    }
+
+    pub fn compiler_available(&self) -> bool {
+        self.compiler_version.is_some()
+    }
+
+    pub fn load(&self, request: &CompileRequest) -> Option<Vec<u8>> {
+        self.load_output(request).map(|output| output.spirv)
+    }
+
+    /// Like [`ShaderCache::load`], with the includes recorded in the entry.
+    pub fn load_output(&self, request: &CompileRequest) -> Option<CompileOutput> {
+        let key = request.key();
+        let meta = fs::read_to_string(self.directory.join(format!("{key}.meta"))).ok()?;
+
+        let mut includes = Vec::new();
+        for line in meta.lines() {
+            match line.split_once(' ') {
+                Some(("compiler", version)) => {
+                    if let Some(compiler_version) = &self.compiler_version {
+                        if version != compiler_version {
+                            return None;
+                        }
+                    }
+                }
+                Some(("include", include)) => {
+                    let (hash, path) = include.split_once(' ')?;
+                    let path = Path::new(request.directory).join(path);
+                    if hash_file(&path)? != hash {
+                        return None;
+                    }
+                    includes.push(path);
+                }
+                _ => return None,
+            }
+        }
+
+        Some(CompileOutput {
+            spirv: fs::read(self.directory.join(format!("{key}.spv"))).ok()?,
+            includes,
+        })
+    }
+
+    /// Stores `spirv` compiled from `request` and the files it included.
+    pub fn store(&self, request: &CompileRequest, spirv: &[u8], includes: &[PathBuf]) -> Result<()> {
+        let key = request.key();
+        let io_error = |path: PathBuf| move |source| Error::Io { path, source };
+
+        fs::create_dir_all(&self.directory).map_err(io_error(self.directory.clone()))?;
+
+        let mut meta = String::new();
+        if let Some(compiler_version) = &self.compiler_version {
+            meta += &format!("compiler {compiler_version}\n");
+        }
+        for include in includes {
+            let hash = hash_file(include).ok_or_else(|| Error::Io {
+                path: include.clone(),
+                source: std::io::ErrorKind::NotFound.into(),
+            })?;
+            meta += &format!("include {hash} {}\n", request.relative(&include.to_string_lossy()));
+        }
+
+        // The metadata goes last, a half written entry is never loaded.
+        let spv_path = self.directory.join(format!("{key}.spv"));
+        fs::write(&spv_path, spirv).map_err(io_error(spv_path))?;
+        let meta_path = self.directory.join(format!("{key}.meta"));
+        fs::write(&meta_path, meta).map_err(io_error(meta_path))?;
+
+        Ok(())
+    }
+
+    /// Loads the SPIR-V of `request` from the cache, or compiles and stores it.
+    /// `#include`s are resolved by DXC, relative to the including file and then
+    /// to the `-I` directories in the request's arguments.
+    pub fn compile(&self, request: &CompileRequest) -> Result<CompileOutput> {
+        if let Some(output) = self.load_output(request) {
+            return Ok(output);
+        }
+
+        let compilation_error = |message: String| Error::ShaderCompilation {
+            path: request.path.into(),
+            entry_point: request.entry_point.to_owned(),
+            message,
+        };
+
+        if !self.compiler_available() {
+            return Err(compilation_error(format!(
+                "Not in the shader cache at {:?} and DXC is not installed",
+                self.directory
+            )));
+        }
+
+        let output = compile_hlsl(request).map_err(|error| {
+            compilation_error(match error {
+                HassleError::CompileError(message) => message,
+                error => error.to_string(),
+            })
+        })?;
+
+        // A cache that cannot be written only costs time on the next launch.
+        if let Err(error) = self.store(request, &output.spirv, &output.includes) {
+            log::warn!("Could not cache {} {}: {error}", request.path, request.entry_point);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Loads the files DXC asks for and records which ones it included. DXC calls
+/// it with each candidate path in turn until one loads.
+#[derive(Default)]
+struct IncludeRecorder {
+    includes: Vec<PathBuf>,
+}
+
+impl DxcIncludeHandler for IncludeRecorder {
+    fn load_source(&mut self, filename: String) -> Option<String> {
+        // DXC joins the including file's directory with `./`.
+        let path: PathBuf = Path::new(&filename)
+            .components()
+            .filter(|component| *component != std::path::Component::CurDir)
+            .collect();
+        let source = fs::read_to_string(&path).ok()?;
+        if !self.includes.contains(&path) {
+            self.includes.push(path);
+        }
+        Some(source)
+    }
+}
+
+/// `hassle_rs::compile_hlsl` with an include handler that records the includes.
+fn compile_hlsl(request: &CompileRequest) -> Result<CompileOutput, HassleError> {
+    let dxc = Dxc::new(None)?;
+    let compiler = dxc.create_compiler()?;
+    let library = dxc.create_library()?;
+
+    let blob = library.create_blob_with_encoding_from_str(request.source)?;
+    let mut recorder = IncludeRecorder::default();
+    let result = compiler.compile(
+        &blob,
+        request.path,
+        request.entry_point,
+        request.profile,
+        request.args,
+        Some(&mut recorder),
+        request.defines,
+    );
+
+    match result {
+        Err((result, _)) => {
+            let error_blob = result.get_error_buffer()?;
+            Err(HassleError::CompileError(
+                library.get_blob_as_string(&error_blob.into())?,
+            ))
+        }
+        Ok(result) => Ok(CompileOutput {
+            spirv: result.get_result()?.to_vec(),
+            includes: recorder.includes,
+        }),
+    }
+}
+
+/// Cache directory from [`CACHE_DIR_ENV`], or [`DEFAULT_DIR`].
+pub fn directory_from_env() -> PathBuf {
+    std::env::var_os(CACHE_DIR_ENV)
+        .filter(|value| !value.is_empty())
+        .map_or_else(|| PathBuf::from(DEFAULT_DIR), PathBuf::from)
+}
+
+/// Driver specific shader object binaries from `vkGetShaderBinaryDataEXT`.
+///
+/// Binaries are only compatible with drivers that report the same
+/// `shaderBinaryUUID` and `shaderBinaryVersion`, so each combination gets its
+/// own directory. A driver may still reject a binary, callers then have to
+/// fall back to SPIR-V and store the new binaries.
+pub struct ShaderBinaryCache {
+    /// `<cache>/binaries/<shaderBinaryUUID>-<shaderBinaryVersion>`
+    pub directory: PathBuf,
+}
+
+impl ShaderBinaryCache {
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        cache_directory: &Path,
+    ) -> ShaderBinaryCache {
+        let mut shader_object_properties = vk::PhysicalDeviceShaderObjectPropertiesEXT::default();
+        let mut properties =
+            vk::PhysicalDeviceProperties2::builder().push_next(&mut shader_object_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+
+        ShaderBinaryCache {
+            directory: cache_directory.join("binaries").join(format!(
+                "{}-{}",
+                hex(&shader_object_properties.shader_binary_uuid),
+                shader_object_properties.shader_binary_version
+            )),
+        }
+    }
+
+    /// Key of stages that are created together, from their SPIR-V.
+    pub fn key(stages: &[&[u8]]) -> String {
+        let mut hasher = Sha256::new();
+        for spirv in stages {
+            hasher.update((spirv.len() as u64).to_le_bytes());
+            hasher.update(spirv);
+        }
+        hex(&hasher.finalize())
+    }
+
+    pub fn load(&self, key: &str, stage_count: usize) -> Option<Vec<Vec<u8>>> {
+        (0..stage_count)
+            .map(|stage| fs::read(self.directory.join(format!("{key}.{stage}.bin"))).ok())
+            .collect()
+    }
+
+    pub fn store(&self, key: &str, binaries: &[Vec<u8>]) -> Result<()> {
+        let io_error = |path: PathBuf| move |source| Error::Io { path, source };
+
+        fs::create_dir_all(&self.directory).map_err(io_error(self.directory.clone()))?;
+        for (stage, binary) in binaries.iter().enumerate() {
+            let path = self.directory.join(format!("{key}.{stage}.bin"));
+            fs::write(&path, binary).map_err(io_error(path))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies the DXC build by the version and commit the compiler reports.
+fn compiler_version() -> Option<String> {
+    // Opening the library hassle-rs loads shares its handle with hassle-rs.
+    let library = unsafe { libloading::Library::new(DXC_LIBRARY) }.ok()?;
+    let create_instance = unsafe { library.get::<DxcCreateInstanceProc>(b"DxcCreateInstance\0") }.ok()?;
+
+    let mut info: ComPtr<IDxcVersionInfo> = ComPtr::new();
+    create_instance(&CLSID_DxcCompiler, &IID_IDxcVersionInfo, info.as_mut_ptr()).result().ok()?;
+    let (mut major, mut minor) = (0, 0);
+    unsafe { info.get_version(&mut major, &mut minor) }.result().ok()?;
+
+    // Releases share the major and minor version, the commit tells them apart.
+    let info: ComPtr<IDxcVersionInfo2> = ComPtr::from(&info);
+    let (mut commit_count, mut commit_hash) = (0, std::ptr::null_mut());
+    if info.is_null() || unsafe { info.get_commit_info(&mut commit_count, &mut commit_hash) }.is_err() {
+        return Some(format!("{major}.{minor}"));
+    }
+    let hash = unsafe { std::ffi::CStr::from_ptr(commit_hash.cast()) }.to_string_lossy().into_owned();
+    unsafe { hassle_rs::os::CoTaskMemFree(commit_hash.cast()) };
+    Some(format!("{major}.{minor}.{commit_count}-{hash}"))
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    Some(hex(&Sha256::digest(fs::read(path).ok()?)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, path::Path};
+
+use ash::vk;
+use bytemuck::Pod;
+use gpu_allocator::MemoryLocation;
+
+use crate::{
+    app::App,
+    error::{Error, Result},
+    manifest::{ProgramDesc, Stage, StageDesc},
+    shader_cache::{CompileRequest, ShaderCache},
+    shaders::{PermutationKey, ProgramCode, StageCode, SHADER_DIR},
+};
+
+/// Entry point of the compute shader wrapping the function under test.
+pub const ENTRY_POINT: &str = "shaderTestMain";
+const GROUP_SIZE: u32 = 64;
+
+/// An HLSL function `Output function(Input input)` to run on the GPU, once
+/// per element of an input buffer.
+///
+/// Inputs are read and outputs written with `vk::RawBufferLoad` and
+/// `vk::RawBufferStore`, so the Rust types have to match the HLSL types'
+/// layout, padding included.
+#[derive(Clone, Debug)]
+pub struct ShaderFunction {
+    /// Declares the function and its types, usually by including the header
+    /// it lives in. Includes are resolved relative to [`SHADER_DIR`].
+    pub source: String,
+    pub function: String,
+    pub input_type: String,
+    pub output_type: String,
+}
+
+impl ShaderFunction {
+    pub fn new(source: &str, function: &str, input_type: &str, output_type: &str) -> ShaderFunction {
+        ShaderFunction {
+            source: source.to_owned(),
+            function: function.to_owned(),
+            input_type: input_type.to_owned(),
+            output_type: output_type.to_owned(),
+        }
+    }
+
+    /// `source` followed by a compute entry point that calls the function for
+    /// every input.
+    pub fn wrapper(&self) -> String {
+        let ShaderFunction {
+            source,
+            function,
+            input_type,
+            output_type,
+        } = self;
+        format!(
+            r#"{source}
+
+struct ShaderTestPushConstants
+{{
+    uint64_t input_address;
+    uint64_t output_address;
+    uint input_stride;
+    uint output_stride;
+    uint count;
+    uint padding;
+}};
+
+[[vk::push_constant]] ShaderTestPushConstants shaderTestPushConstants;
+
+[numthreads({GROUP_SIZE}, 1, 1)]
+void {ENTRY_POINT}(uint3 id: SV_DispatchThreadID)
+{{
+    if (id.x < shaderTestPushConstants.count)
+    {{
+        {input_type} input = vk::RawBufferLoad<{input_type}>(
+            shaderTestPushConstants.input_address + id.x * shaderTestPushConstants.input_stride);
+        {output_type} output = {function}(input);
+        vk::RawBufferStore<{output_type}>(
+            shaderTestPushConstants.output_address + id.x * shaderTestPushConstants.output_stride, output);
+    }}
+}}
+"#
+        )
+    }
+
+    /// Compiles [`ShaderFunction::wrapper`] like a compute stage of the
+    /// manifest, going through `cache`.
+    pub fn compile(&self, cache: &ShaderCache) -> Result<ProgramCode> {
+        let path = Path::new(SHADER_DIR).join(format!("{}.test.hlsl", self.function));
+        let stage = StageDesc {
+            stage: Stage::Compute,
+            source: path.clone(),
+            entry_point: ENTRY_POINT.to_owned(),
+            profile: "cs_6_6".to_owned(),
+            defines: BTreeMap::new(),
+            args: Vec::new(),
+        };
+        let output = cache.compile(&CompileRequest {
+            directory: SHADER_DIR,
+            path: &path.to_string_lossy(),
+            source: &self.wrapper(),
+            entry_point: &stage.entry_point,
+            profile: &stage.profile,
+            args: &["-spirv"],
+            defines: &[],
+        })?;
+
+        Ok(ProgramCode {
+            desc: ProgramDesc {
+                name: format!("{} test", self.function),
+                stages: vec![stage],
+                fallback: None,
+                axes: Vec::new(),
+                constants: Vec::new(),
+            },
+            key: PermutationKey::new(),
+            stages: vec![StageCode {
+                stage: Stage::Compute,
+                spirv: output.spirv,
+                dependencies: output.includes,
+            }],
+        })
+    }
+
+    /// Runs the function over `inputs` on the device of `app` and returns its
+    /// outputs in the same order. Waits for the device, so only use it outside
+    /// the frame loop. Zero-sized `Input` and `Output` types are an error.
+    pub fn run<Input: Pod, Output: Pod>(&self, app: &mut App, inputs: &[Input]) -> Result<Vec<Output>> {
+        if std::mem::size_of::<Input>() == 0 {
+            return Err(Error::ZeroSized { type_name: std::any::type_name::<Input>() });
+        }
+        if std::mem::size_of::<Output>() == 0 {
+            return Err(Error::ZeroSized { type_name: std::any::type_name::<Output>() });
+        }
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let code = self.compile(&ShaderCache::from_env())?;
+
+        let input_bytes: &[u8] = bytemuck::cast_slice(inputs);
+        let output_size = inputs.len() * std::mem::size_of::<Output>();
+        let renderer = &mut app.renderer;
+        let mut input = renderer.create_buffer(
+            input_bytes.len() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            "Shader test input",
+        )?;
+        let mut output = match renderer.create_buffer(
+            output_size as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::GpuToCpu,
+            "Shader test output",
+        ) {
+            Ok(output) => output,
+            Err(error) => {
+                renderer.destroy_buffer(&mut input);
+                return Err(error);
+            }
+        };
+        input.allocation.mapped_slice_mut().expect("CpuToGpu memory is mapped")[..input_bytes.len()]
+            .copy_from_slice(input_bytes);
+
+        let count = inputs.len() as u32;
+        let mut push_constants = Vec::new();
+        push_constants.extend_from_slice(&input.address.to_le_bytes());
+        push_constants.extend_from_slice(&output.address.to_le_bytes());
+        push_constants.extend_from_slice(&(std::mem::size_of::<Input>() as u32).to_le_bytes());
+        push_constants.extend_from_slice(&(std::mem::size_of::<Output>() as u32).to_le_bytes());
+        push_constants.extend_from_slice(&count.to_le_bytes());
+        push_constants.extend_from_slice(&0u32.to_le_bytes());
+
+        let result = renderer
+            .dispatch_once(code, push_constants, [count.div_ceil(GROUP_SIZE), 1, 1])
+            .map(|()| {
+                output.allocation.mapped_slice().expect("GpuToCpu memory is mapped")[..output_size]
+                    .chunks_exact(std::mem::size_of::<Output>())
+                    .map(bytemuck::pod_read_unaligned)
+                    .collect()
+            });
+        renderer.destroy_buffer(&mut input);
+        renderer.destroy_buffer(&mut output);
+        result
+    }
+}
@@ -1,80 +1,1098 @@
-use std::{ffi::CString, fs};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::CString,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
 
 use ash::vk;
-use hassle_rs::compile_hlsl;
 
+use crate::{
+    debug::DebugNames,
+    error::{Error, Result},
+    front_end::compile_stage,
+    manifest::{ConstantValue, Manifest, ProgramDesc, Stage, MANIFEST_FILE},
+    reflection::{Reflection, ShaderLayout},
+    renderer::DeviceExtensions,
+    shader_cache::{ShaderBinaryCache, ShaderCache},
+};
+
+/// How the stages of a program are bound. Shader objects are used when
+/// `VK_EXT_shader_object` is available, otherwise the same SPIR-V is built
+/// into graphics pipelines with dynamic rendering and fully dynamic state.
+pub enum ShaderBackend {
+    ShaderObjects {
+        shaders: Vec<vk::ShaderEXT>,
+    },
+    Pipelines {
+        modules: Vec<vk::ShaderModule>,
+        /// Built on first use, one per color attachment format. A compute
+        /// program has a single one under `vk::Format::UNDEFINED`.
+        pipelines: HashMap<vk::Format, vk::Pipeline>,
+    },
+}
+
+/// Refers to a program of the [`Shaders`] it was looked up in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProgramHandle(usize);
+
+/// Selects a variant of a program by the values of its axes and
+/// specialization constants. Whatever is not set keeps its default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PermutationKey {
+    pub defines: BTreeMap<String, String>,
+    pub constants: BTreeMap<String, ConstantValue>,
+}
+
+impl PermutationKey {
+    pub fn new() -> PermutationKey {
+        PermutationKey::default()
+    }
+
+    /// Defines a flag axis.
+    pub fn flag(self, define: &str) -> PermutationKey {
+        self.define(define, "1")
+    }
+
+    /// Picks one of the values of an axis.
+    pub fn define(mut self, define: &str, value: &str) -> PermutationKey {
+        self.defines.insert(define.to_owned(), value.to_owned());
+        self
+    }
+
+    pub fn constant(mut self, name: &str, value: impl Into<ConstantValue>) -> PermutationKey {
+        self.constants.insert(name.to_owned(), value.into());
+        self
+    }
+
+    /// Every combination of the axes of `desc`, each with default constants.
+    /// The first one is the default variant.
+    pub fn all(desc: &ProgramDesc) -> Vec<PermutationKey> {
+        let mut keys = vec![PermutationKey::new()];
+        for axis in &desc.axes {
+            let values: Vec<Option<&str>> = if axis.is_flag() {
+                vec![None, Some("1")]
+            } else {
+                axis.values.iter().map(|value| Some(value.as_str())).collect()
+            };
+            keys = keys
+                .iter()
+                .flat_map(|key| {
+                    values.iter().map(move |value| match value {
+                        Some(value) => key.clone().define(&axis.define, value),
+                        None => key.clone(),
+                    })
+                })
+                .collect();
+        }
+        keys
+    }
+
+    /// The key with every axis and constant of `desc` set, to defaults where
+    /// this one leaves them out. Unset flags stay out of the defines.
+    pub fn resolve(&self, desc: &ProgramDesc) -> Result<PermutationKey> {
+        let error = |message: String| Error::Manifest {
+            path: Path::new(SHADER_DIR).join(MANIFEST_FILE),
+            message: format!("Program {:?}: {message}", desc.name),
+        };
+        if let Some(define) = self.defines.keys().find(|define| desc.axis(define).is_none()) {
+            return Err(error(format!("No axis {define:?}")));
+        }
+        if let Some(name) = self.constants.keys().find(|name| desc.constant(name).is_none()) {
+            return Err(error(format!("No constant {name:?}")));
+        }
+
+        let mut defines = BTreeMap::new();
+        for axis in &desc.axes {
+            let value = match self.defines.get(&axis.define) {
+                None if axis.is_flag() => continue,
+                None => &axis.values[0],
+                Some(value) if axis.is_flag() && value == "1" => value,
+                Some(value) if axis.values.contains(value) => value,
+                Some(value) => {
+                    return Err(error(format!("{value:?} is not a value of axis {:?}", axis.define)))
+                }
+            };
+            defines.insert(axis.define.clone(), value.clone());
+        }
+
+        let mut constants = BTreeMap::new();
+        for constant in &desc.constants {
+            let value = self.constants.get(&constant.name).copied().unwrap_or(constant.default);
+            if !value.same_type(constant.default) {
+                return Err(error(format!(
+                    "Constant {:?} takes values like {:?}, not {value:?}",
+                    constant.name, constant.default
+                )));
+            }
+            constants.insert(constant.name.clone(), value);
+        }
+
+        Ok(PermutationKey { defines, constants })
+    }
+}
+
+/// Specialization constants of a variant, the same for each of its stages.
+#[derive(Default)]
+struct Specialization {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl Specialization {
+    /// `key` has to be resolved against `desc`.
+    fn new(desc: &ProgramDesc, key: &PermutationKey) -> Specialization {
+        let mut specialization = Specialization::default();
+        for constant in &desc.constants {
+            specialization.entries.push(vk::SpecializationMapEntry {
+                constant_id: constant.id,
+                offset: specialization.data.len() as u32,
+                size: 4,
+            });
+            let value = key.constants.get(&constant.name).copied().unwrap_or(constant.default);
+            specialization.data.extend(value.bits().to_ne_bytes());
+        }
+        specialization
+    }
+
+    fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
+
+/// The linked stages of one variant of a manifest program and their layouts.
+pub struct Program {
+    pub name: String,
+    /// Resolved key of the variant.
+    pub key: PermutationKey,
+    /// Stage of each of the backend's shaders or modules, in link order.
+    pub stages: Vec<vk::ShaderStageFlags>,
+    /// Entry point of each stage, in the same order.
+    entry_points: Vec<CString>,
+    specialization: Specialization,
+
+    pub backend: ShaderBackend,
+    /// Reflected from the SPIR-V of all stages.
+    pub layout: ShaderLayout,
+    /// One per set up to the highest one used, unused sets are empty.
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub pipeline_layout: vk::PipelineLayout,
+}
+
+/// Registry of every program in the shader manifest. Programs start out with
+/// their default variant, others are compiled on first use by
+/// [`Shaders::permutation`]. Without mesh shader support, programs with mesh
+/// stages are not created and their names resolve to their fallbacks.
 pub struct Shaders {
-    pub shaders: Vec<vk::ShaderEXT>,
-    pub layouts: Vec<vk::PipelineLayout>,
+    device: ash::Device,
+    debug: DebugNames,
+    extensions: DeviceExtensions,
+
+    programs: Vec<Program>,
+    handles: HashMap<String, ProgramHandle>,
+    /// Every variant created so far, by program name and resolved key.
+    variants: HashMap<(String, PermutationKey), ProgramHandle>,
+    /// What variants are compiled from.
+    descs: HashMap<String, ProgramDesc>,
+    directory: PathBuf,
+    include_paths: Vec<PathBuf>,
+    dependencies: BTreeSet<PathBuf>,
+    /// Compiles variants, probed for DXC once.
+    cache: ShaderCache,
 }
 
-impl Shaders {
+/// State that pipelines leave dynamic. Everything the renderer sets in
+/// [`Shaders::set_dynamic_state`] for both backends.
+const DYNAMIC_STATES: [vk::DynamicState; 14] = [
+    vk::DynamicState::VIEWPORT_WITH_COUNT,
+    vk::DynamicState::SCISSOR_WITH_COUNT,
+    vk::DynamicState::PRIMITIVE_TOPOLOGY,
+    vk::DynamicState::CULL_MODE,
+    vk::DynamicState::FRONT_FACE,
+    vk::DynamicState::DEPTH_TEST_ENABLE,
+    vk::DynamicState::DEPTH_WRITE_ENABLE,
+    vk::DynamicState::DEPTH_COMPARE_OP,
+    vk::DynamicState::DEPTH_BOUNDS_TEST_ENABLE,
+    vk::DynamicState::STENCIL_TEST_ENABLE,
+    vk::DynamicState::STENCIL_OP,
+    vk::DynamicState::RASTERIZER_DISCARD_ENABLE,
+    vk::DynamicState::DEPTH_BIAS_ENABLE,
+    vk::DynamicState::PRIMITIVE_RESTART_ENABLE,
+];
+
+/// Directory the shader sources are loaded from, relative to the working directory.
+pub const SHADER_DIR: &str = "shaders";
+
+/// Compiled SPIR-V of one stage.
+pub struct StageCode {
+    pub stage: Stage,
+    pub spirv: Vec<u8>,
+    /// The source and every file it included.
+    pub dependencies: Vec<PathBuf>,
+}
+
+/// Compiled SPIR-V of every stage of one variant of a program, in link order.
+pub struct ProgramCode {
+    /// As declared in the manifest, without the variant's defines.
+    pub desc: ProgramDesc,
+    /// Resolved key of the variant.
+    pub key: PermutationKey,
+    pub stages: Vec<StageCode>,
+}
+
+impl ProgramCode {
+    /// Compiles the stages of the variant `key` of `desc`, whose sources are
+    /// relative to `directory`, each with the front end of its language.
+    pub fn compile(
+        directory: &Path,
+        include_paths: &[PathBuf],
+        desc: &ProgramDesc,
+        key: &PermutationKey,
+        cache: &ShaderCache,
+    ) -> Result<ProgramCode> {
+        let key = key.resolve(desc)?;
+        let stages = desc
+            .with_defines(&key.defines)
+            .stages
+            .iter()
+            .map(|stage| {
+                let output = compile_stage(directory, include_paths, stage, cache)?;
+                Ok(StageCode {
+                    stage: stage.stage,
+                    spirv: output.spirv,
+                    dependencies: std::iter::once(directory.join(&stage.source))
+                        .chain(output.includes)
+                        .collect(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ProgramCode {
+            desc: desc.clone(),
+            key,
+            stages,
+        })
+    }
+
+    pub fn has_mesh_stages(&self) -> bool {
+        self.desc.has_mesh_stages()
+    }
+
+    /// Reflects every stage, checking that each has a single entry point of
+    /// its stage, and merges their layouts.
+    pub fn reflect(&self) -> Result<(Vec<Reflection>, ShaderLayout)> {
+        let reflections = self
+            .stages
+            .iter()
+            .map(|code| {
+                let reflection = Reflection::new(&code.spirv)?;
+                let stage = code.stage.flags();
+                if reflection.stages() != stage || reflection.entry_points.len() != 1 {
+                    return Err(Error::Reflection(format!(
+                        "Expected a single {stage:?} entry point in {}, found {:?}",
+                        self.desc.name, reflection.entry_points
+                    )));
+                }
+                Ok(reflection)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let layout = ShaderLayout::merge(&reflections)?;
+        Ok((reflections, layout))
+    }
+
+    /// Files that change the program's SPIR-V when edited.
+    pub fn dependencies(&self) -> impl Iterator<Item = &Path> {
+        self.stages
+            .iter()
+            .flat_map(|code| code.dependencies.iter().map(PathBuf::as_path))
+    }
+
+    pub fn stage(&self, stage: Stage) -> Option<&[u8]> {
+        self.stages
+            .iter()
+            .find(|code| code.stage == stage)
+            .map(|code| code.spirv.as_slice())
+    }
+}
+
+/// Compiled SPIR-V of the default variant of every program in the manifest.
+pub struct ShaderCode {
+    pub programs: Vec<ProgramCode>,
+    /// Where the sources and include paths are relative to.
+    pub directory: PathBuf,
+    /// See [`Manifest::include_paths`].
+    pub include_paths: Vec<PathBuf>,
+}
+
+impl ShaderCode {
+    /// Reads the manifest in [`SHADER_DIR`] and compiles its programs, going
+    /// through the shader cache configured by the environment.
+    pub fn compile() -> Result<ShaderCode> {
+        Self::compile_with(&ShaderCache::from_env())
+    }
+
+    pub fn compile_with(cache: &ShaderCache) -> Result<ShaderCode> {
+        let directory = Path::new(SHADER_DIR);
+        let manifest = Manifest::load(&directory.join(MANIFEST_FILE))?;
+
+        let programs = manifest
+            .programs
+            .iter()
+            .map(|program| {
+                ProgramCode::compile(
+                    directory,
+                    &manifest.include_paths,
+                    program,
+                    &PermutationKey::default(),
+                    cache,
+                )
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ShaderCode {
+            programs,
+            directory: directory.to_owned(),
+            include_paths: manifest.include_paths,
+        })
+    }
+
+    /// The manifest and the dependencies of every program.
+    pub fn dependencies(&self) -> BTreeSet<PathBuf> {
+        std::iter::once(Path::new(SHADER_DIR).join(MANIFEST_FILE))
+            .chain(
+                self.programs
+                    .iter()
+                    .flat_map(|program| program.dependencies().map(Path::to_owned)),
+            )
+            .collect()
+    }
+
+    pub fn program(&self, name: &str) -> Option<&ProgramCode> {
+        self.programs.iter().find(|program| program.desc.name == name)
+    }
+}
+
+/// Like `ShaderObject::create_shaders`, but destroys the shaders that were
+/// created when others failed. That happens when only some cached binaries are
+/// incompatible.
+unsafe fn create_shaders(
+    loader: &ash::extensions::ext::ShaderObject,
+    create_infos: &[vk::ShaderCreateInfoEXT],
+) -> ash::prelude::VkResult<Vec<vk::ShaderEXT>> {
+    let mut shaders = vec![vk::ShaderEXT::null(); create_infos.len()];
+    let result = (loader.fp().create_shaders_ext)(
+        loader.device(),
+        create_infos.len() as u32,
+        create_infos.as_ptr(),
+        std::ptr::null(),
+        shaders.as_mut_ptr(),
+    );
+
+    if result != vk::Result::SUCCESS {
+        for shader in shaders.into_iter().filter(|shader| *shader != vk::ShaderEXT::null()) {
+            loader.destroy_shader(shader, None);
+        }
+        return Err(result);
+    }
+
+    Ok(shaders)
+}
+
+impl Program {
+    /// Links the stages of `code` and creates their layouts. With shader
+    /// objects and a `binary_cache`, driver binaries of the same SPIR-V and
+    /// specialization are tried first.
     pub fn new(
         device: &ash::Device,
-        shader_object_loader: &ash::extensions::ext::ShaderObject,
-    ) -> Shaders {
+        extensions: &DeviceExtensions,
+        binary_cache: Option<&ShaderBinaryCache>,
+        debug: &DebugNames,
+        code: &ProgramCode,
+    ) -> Result<Program> {
+        let name = &code.desc.name;
+        let stages: Vec<vk::ShaderStageFlags> =
+            code.stages.iter().map(|stage| stage.stage.flags()).collect();
+        let spirv: Vec<&[u8]> = code.stages.iter().map(|stage| stage.spirv.as_slice()).collect();
+
+        let (reflections, layout) = code.reflect()?;
+        let entry_points: Vec<CString> = reflections
+            .iter()
+            .map(|reflection| {
+                CString::new(reflection.entry_points[0].name.as_str()).expect("CString::new failed")
+            })
+            .collect();
+        let push_constant_ranges = &layout.push_constant_ranges;
+        let specialization = Specialization::new(&code.desc, &code.key);
+        let specialization_info = specialization.info();
+
         unsafe {
-            let code_hlsl = fs::read_to_string("./shaders/triangle.hlsl")
-                .expect("Should have been able to read the file");
-
-            let vertex_spirv = compile_hlsl(
-                "shaders/triangle.hlsl",
-                &code_hlsl,
-                "vertexMain",
-                "vs_6_6",
-                &vec!["-spirv"],
-                &vec![],
-            )
-            .expect("Should have been able to compile the shader");
-            let vertex_name = CString::new("vertexMain").expect("CString::new failed");
-            let vertex = vk::ShaderCreateInfoEXT::builder()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .flags(vk::ShaderCreateFlagsEXT::LINK_STAGE)
-                .next_stage(vk::ShaderStageFlags::FRAGMENT)
-                .code_type(vk::ShaderCodeTypeEXT::SPIRV)
-                .code(&vertex_spirv)
-                .push_constant_ranges(&[vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::VERTEX,
-                    offset: 0,
-                    size: 64,
-                }])
-                .name(&vertex_name)
-                .build();
-
-            let fragment_spirv = compile_hlsl(
-                "shaders/triangle.hlsl",
-                &code_hlsl,
-                "pixelMain",
-                "ps_6_6",
-                &vec!["-spirv"],
-                &vec![],
+            let set_count = layout.sets.keys().last().map_or(0, |set| set + 1);
+            let mut set_layouts = Vec::new();
+            let mut shaders = Vec::new();
+            let mut modules = Vec::new();
+            // Everything created so far is destroyed again if a later step fails.
+            let result = (|| -> Result<vk::PipelineLayout> {
+                for set in 0..set_count {
+                    let bindings = layout.sets.get(&set).map_or(&[][..], Vec::as_slice);
+                    set_layouts.push(device.create_descriptor_set_layout(
+                        &vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings),
+                        None,
+                    )?);
+                }
+
+                match &extensions.shader_object {
+                    Some(loader) => {
+                        // Stages created together are linked, each naming the one after it.
+                        let link = if stages.len() > 1 {
+                            vk::ShaderCreateFlagsEXT::LINK_STAGE
+                        } else {
+                            vk::ShaderCreateFlagsEXT::empty()
+                        };
+                        let has_task = stages.contains(&vk::ShaderStageFlags::TASK_EXT);
+                        let create_infos: Vec<vk::ShaderCreateInfoEXT> = stages
+                            .iter()
+                            .enumerate()
+                            .map(|(i, stage)| {
+                                let flags = if *stage == vk::ShaderStageFlags::MESH_EXT && !has_task {
+                                    link | vk::ShaderCreateFlagsEXT::NO_TASK_SHADER
+                                } else {
+                                    link
+                                };
+                                vk::ShaderCreateInfoEXT::builder()
+                                    .stage(*stage)
+                                    .flags(flags)
+                                    .next_stage(stages.get(i + 1).copied().unwrap_or_default())
+                                    .code_type(vk::ShaderCodeTypeEXT::SPIRV)
+                                    .code(spirv[i])
+                                    .push_constant_ranges(push_constant_ranges)
+                                    .set_layouts(&set_layouts)
+                                    .specialization_info(&specialization_info)
+                                    .name(&entry_points[i])
+                                    .build()
+                            })
+                            .collect();
+                        let mut key_inputs = spirv.clone();
+                        if !specialization.data.is_empty() {
+                            key_inputs.push(&specialization.data);
+                        }
+                        let key = ShaderBinaryCache::key(&key_inputs);
+
+                        let cached = binary_cache
+                            .and_then(|cache| cache.load(&key, create_infos.len()))
+                            .and_then(|binaries| {
+                                let binary_infos: Vec<vk::ShaderCreateInfoEXT> = create_infos
+                                    .iter()
+                                    .zip(&binaries)
+                                    .map(|(create_info, binary)| {
+                                        let mut create_info = *create_info;
+                                        create_info.code_type = vk::ShaderCodeTypeEXT::BINARY;
+                                        create_info.code_size = binary.len();
+                                        create_info.p_code = binary.as_ptr().cast();
+                                        create_info
+                                    })
+                                    .collect();
+
+                                match create_shaders(loader, &binary_infos) {
+                                    Ok(shaders) => Some(shaders),
+                                    Err(error) => {
+                                        log::info!("Cached shader binaries of {name} rejected ({error}), using SPIR-V");
+                                        None
+                                    }
+                                }
+                            });
+
+                        shaders = match cached {
+                            Some(shaders) => shaders,
+                            None => {
+                                let shaders = create_shaders(loader, &create_infos)?;
+                                if let Some(cache) = binary_cache {
+                                    let stored = shaders
+                                        .iter()
+                                        .map(|shader| loader.get_shader_binary_data(*shader).map_err(Error::from))
+                                        .collect::<Result<Vec<_>>>()
+                                        .and_then(|binaries| cache.store(&key, &binaries));
+                                    if let Err(error) = stored {
+                                        log::warn!("Could not cache shader binaries of {name}: {error}");
+                                    }
+                                }
+                                shaders
+                            }
+                        };
+                        for (shader, entry_point) in shaders.iter().zip(&entry_points) {
+                            debug.name(*shader, &format!("{name} {}", entry_point.to_string_lossy()));
+                        }
+                    }
+                    None => {
+                        for (spirv, entry_point) in spirv.iter().zip(&entry_points) {
+                            let words = ash::util::read_spv(&mut Cursor::new(spirv))
+                                .map_err(|error| Error::Reflection(format!("Invalid SPIR-V: {error}")))?;
+                            let module = device.create_shader_module(
+                                &vk::ShaderModuleCreateInfo::builder().code(&words),
+                                None,
+                            )?;
+                            debug.name(module, &format!("{name} {}", entry_point.to_string_lossy()));
+                            modules.push(module);
+                        }
+                    }
+                }
+
+                let pipeline_layout = device.create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::builder()
+                        .set_layouts(&set_layouts)
+                        .push_constant_ranges(push_constant_ranges),
+                    None,
+                )?;
+                debug.name(pipeline_layout, &format!("{name} layout"));
+                Ok(pipeline_layout)
+            })();
+
+            let pipeline_layout = match result {
+                Ok(pipeline_layout) => pipeline_layout,
+                Err(error) => {
+                    if let Some(loader) = &extensions.shader_object {
+                        shaders.drain(..).for_each(|s| loader.destroy_shader(s, None));
+                    }
+                    modules.drain(..).for_each(|m| device.destroy_shader_module(m, None));
+                    set_layouts
+                        .drain(..)
+                        .for_each(|l| device.destroy_descriptor_set_layout(l, None));
+                    return Err(error);
+                }
+            };
+            let backend = match &extensions.shader_object {
+                Some(_) => ShaderBackend::ShaderObjects { shaders },
+                None => ShaderBackend::Pipelines {
+                    modules,
+                    pipelines: HashMap::new(),
+                },
+            };
+
+            Ok(Program {
+                name: name.clone(),
+                key: code.key.clone(),
+                stages,
+                entry_points,
+                specialization,
+
+                backend,
+                layout,
+                set_layouts,
+                pipeline_layout,
+            })
+        }
+    }
+
+    /// Draws with `vkCmdDrawMeshTasksEXT` instead of `vkCmdDraw`.
+    pub fn has_mesh_stages(&self) -> bool {
+        self.stages.contains(&vk::ShaderStageFlags::MESH_EXT)
+    }
+
+    pub fn is_compute(&self) -> bool {
+        self.stages == [vk::ShaderStageFlags::COMPUTE]
+    }
+
+    fn create_compute_pipeline(
+        device: &ash::Device,
+        module: vk::ShaderModule,
+        entry_point: &CString,
+        specialization: &vk::SpecializationInfo,
+        layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let create_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .module(module)
+                    .name(entry_point)
+                    .specialization_info(specialization)
+                    .build(),
             )
-            .expect("Should have been able to compile the shader");
-            let fragment_name = CString::new("pixelMain").expect("CString::new failed");
-            let fragment = vk::ShaderCreateInfoEXT::builder()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .next_stage(vk::ShaderStageFlags::empty())
-                .flags(vk::ShaderCreateFlagsEXT::LINK_STAGE)
-                .code_type(vk::ShaderCodeTypeEXT::SPIRV)
-                .code(&fragment_spirv)
-                .name(&fragment_name)
-                .build();
-
-            let shaders = shader_object_loader
-                .create_shaders(&[vertex, fragment], None)
-                .expect("Could not compile shaders");
-
-            let layouts = [
-                vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&[vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::VERTEX,
-                    offset: 0,
-                    size: 64,
-                }]).build()
-            ];
-
-            let layouts: Vec<vk::PipelineLayout> = layouts.map(|create_info| device.create_pipeline_layout(&create_info, None).expect("")).to_vec();            
-
-            Shaders { shaders, layouts }
+            .layout(layout)
+            .build();
+
+        let pipelines = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+                .map_err(|(_, error)| error)?
+        };
+
+        Ok(pipelines[0])
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        stages: &[vk::ShaderStageFlags],
+        modules: &[vk::ShaderModule],
+        entry_points: &[CString],
+        specialization: &vk::SpecializationInfo,
+        layout: vk::PipelineLayout,
+        color_format: vk::Format,
+    ) -> Result<vk::Pipeline> {
+        let stages: Vec<vk::PipelineShaderStageCreateInfo> = stages
+            .iter()
+            .zip(modules)
+            .zip(entry_points)
+            .map(|((stage, module), entry_point)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(*stage)
+                    .module(*module)
+                    .name(entry_point)
+                    .specialization_info(specialization)
+                    .build()
+            })
+            .collect();
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport = vk::PipelineViewportStateCreateInfo::default();
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0);
+        let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default();
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(&color_blend_attachments);
+        // Mesh pipelines have no input assembly whose state could be dynamic.
+        let mesh = stages.iter().any(|stage| stage.stage == vk::ShaderStageFlags::MESH_EXT);
+        let dynamic_states: Vec<vk::DynamicState> = DYNAMIC_STATES
+            .into_iter()
+            .filter(|state| {
+                !mesh
+                    || !matches!(
+                        *state,
+                        vk::DynamicState::PRIMITIVE_TOPOLOGY | vk::DynamicState::PRIMITIVE_RESTART_ENABLE
+                    )
+            })
+            .collect();
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
+
+        let color_formats = [color_format];
+        let mut rendering = vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(&color_formats);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(layout)
+            .push_next(&mut rendering)
+            .build();
+
+        let pipelines = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[create_info], None)
+                .map_err(|(_, error)| error)?
+        };
+
+        Ok(pipelines[0])
+    }
+
+    fn destroy(&mut self, device: &ash::Device, extensions: &DeviceExtensions) {
+        unsafe {
+            match &mut self.backend {
+                ShaderBackend::ShaderObjects { shaders } => {
+                    let loader = extensions
+                        .shader_object
+                        .as_ref()
+                        .expect("Shader objects without a loader");
+                    shaders.drain(..).for_each(|s| loader.destroy_shader(s, None));
+                }
+                ShaderBackend::Pipelines { modules, pipelines } => {
+                    pipelines
+                        .drain()
+                        .for_each(|(_, p)| device.destroy_pipeline(p, None));
+                    modules
+                        .drain(..)
+                        .for_each(|m| device.destroy_shader_module(m, None));
+                }
+            }
+
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.pipeline_layout = vk::PipelineLayout::null();
+            self.set_layouts
+                .drain(..)
+                .for_each(|l| device.destroy_descriptor_set_layout(l, None));
+        }
+    }
+}
+
+impl Shaders {
+    pub fn new(
+        device: &ash::Device,
+        extensions: &DeviceExtensions,
+        binary_cache: Option<&ShaderBinaryCache>,
+        debug: &DebugNames,
+    ) -> Result<Shaders> {
+        Self::from_code(
+            device,
+            extensions,
+            binary_cache,
+            debug,
+            &ShaderCode::compile()?,
+        )
+    }
+
+    /// Creates every program of `code`. Nothing is kept if one fails.
+    pub fn from_code(
+        device: &ash::Device,
+        extensions: &DeviceExtensions,
+        binary_cache: Option<&ShaderBinaryCache>,
+        debug: &DebugNames,
+        code: &ShaderCode,
+    ) -> Result<Shaders> {
+        let mut shaders = Shaders {
+            device: device.clone(),
+            debug: debug.clone(),
+            extensions: extensions.clone(),
+
+            programs: Vec::new(),
+            handles: HashMap::new(),
+            variants: HashMap::new(),
+            descs: code
+                .programs
+                .iter()
+                .map(|program| (program.desc.name.clone(), program.desc.clone()))
+                .collect(),
+            directory: code.directory.clone(),
+            include_paths: code.include_paths.clone(),
+            dependencies: code.dependencies(),
+            cache: ShaderCache::from_env(),
+        };
+        let mesh_shaders = extensions.mesh_shader.is_some();
+
+        for program in &code.programs {
+            if program.has_mesh_stages() && !mesh_shaders {
+                continue;
+            }
+            match Program::new(device, extensions, binary_cache, debug, program) {
+                Ok(program) => {
+                    let handle = shaders.add(program);
+                    shaders.handles.insert(shaders.program(handle).name.clone(), handle);
+                }
+                Err(error) => {
+                    shaders.destroy();
+                    return Err(error);
+                }
+            }
+        }
+
+        // The manifest checked that fallbacks exist and have no mesh stages.
+        let skipped = code
+            .programs
+            .iter()
+            .filter(|program| program.has_mesh_stages() && !mesh_shaders);
+        for program in skipped {
+            let name = &program.desc.name;
+            match program.desc.fallback.as_ref().and_then(|fallback| shaders.handle(fallback)) {
+                Some(handle) => {
+                    log::info!("Mesh shaders are not supported, {name} uses its fallback");
+                    shaders.handles.insert(name.clone(), handle);
+                }
+                None => log::info!("Mesh shaders are not supported, skipping {name}"),
+            }
+        }
+
+        Ok(shaders)
+    }
+
+    /// Registers a created variant under its name and key.
+    fn add(&mut self, program: Program) -> ProgramHandle {
+        let handle = ProgramHandle(self.programs.len());
+        self.variants
+            .insert((program.name.clone(), program.key.clone()), handle);
+        self.programs.push(program);
+        handle
+    }
+
+    /// Handle of the variant `key` of the program `handle` belongs to. The
+    /// variant is compiled and created on first use, going through the shader
+    /// cache. Keys are relative to the program's defaults, also when `handle`
+    /// is a variant itself.
+    pub fn permutation(
+        &mut self,
+        handle: ProgramHandle,
+        key: &PermutationKey,
+        binary_cache: Option<&ShaderBinaryCache>,
+    ) -> Result<ProgramHandle> {
+        let desc = &self.descs[&self.programs[handle.0].name];
+        let key = key.resolve(desc)?;
+        if let Some(variant) = self.variants.get(&(desc.name.clone(), key.clone())) {
+            return Ok(*variant);
+        }
+
+        let code = ProgramCode::compile(&self.directory, &self.include_paths, desc, &key, &self.cache)?;
+        self.dependencies.extend(code.dependencies().map(Path::to_owned));
+        let program = Program::new(&self.device, &self.extensions, binary_cache, &self.debug, &code)?;
+        Ok(self.add(program))
+    }
+
+    /// Creates the variants `previous` had, in the order it created them, so
+    /// their handles stay valid when [`Shaders::keeps_handles_of`] holds.
+    pub fn recreate_variants(
+        &mut self,
+        previous: &Shaders,
+        binary_cache: Option<&ShaderBinaryCache>,
+    ) -> Result<()> {
+        let mut variants: Vec<_> = previous.variants.iter().collect();
+        variants.sort_by_key(|(_, handle)| handle.0);
+        for ((name, key), _) in variants {
+            if let Some(handle) = self.handle(name) {
+                self.permutation(handle, key, binary_cache)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Files the shaders were compiled from, see [`ShaderCode::dependencies`],
+    /// including the includes of variants created so far.
+    pub fn dependencies(&self) -> &BTreeSet<PathBuf> {
+        &self.dependencies
+    }
+
+    /// Handle of the program called `name` in the manifest. The renderer only
+    /// accepts reloaded shaders that keep every handle, see
+    /// [`Shaders::keeps_handles_of`].
+    pub fn handle(&self, name: &str) -> Option<ProgramHandle> {
+        self.handles.get(name).copied()
+    }
+
+    /// Like [`Shaders::handle`], but a missing program is an error.
+    pub fn require(&self, name: &str) -> Result<ProgramHandle> {
+        self.handle(name).ok_or_else(|| Error::Manifest {
+            path: Path::new(SHADER_DIR).join(MANIFEST_FILE),
+            message: format!("No program called {name:?}"),
+        })
+    }
+
+    /// Whether every program and variant of `previous` has the same handle
+    /// here, so handles looked up in `previous` can be used with these shaders.
+    pub fn keeps_handles_of(&self, previous: &Shaders) -> bool {
+        previous
+            .handles
+            .iter()
+            .all(|(name, handle)| self.handle(name) == Some(*handle))
+            && previous
+                .variants
+                .iter()
+                .all(|(variant, handle)| self.variants.get(variant) == Some(handle))
+    }
+
+    pub fn program(&self, handle: ProgramHandle) -> &Program {
+        &self.programs[handle.0]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Program> {
+        self.handle(name).map(|handle| self.program(handle))
+    }
+
+    pub fn programs(&self) -> impl Iterator<Item = &Program> {
+        self.programs.iter()
+    }
+
+    /// Binds every stage of a graphics program for drawing into `color_format`.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` has to be recording and come from the device of these
+    /// shaders.
+    pub unsafe fn bind(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        handle: ProgramHandle,
+        color_format: vk::Format,
+    ) -> Result<()> {
+        self.check_kind(handle, false)?;
+        self.bind_program(command_buffer, handle, color_format)
+    }
+
+    /// Binds a compute program for dispatching.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` has to be recording and come from the device of these
+    /// shaders.
+    pub unsafe fn bind_compute(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        handle: ProgramHandle,
+    ) -> Result<()> {
+        self.check_kind(handle, true)?;
+        self.bind_program(command_buffer, handle, vk::Format::UNDEFINED)
+    }
+
+    fn check_kind(&self, handle: ProgramHandle, compute: bool) -> Result<()> {
+        let program = &self.programs[handle.0];
+        if program.is_compute() == compute {
+            return Ok(());
+        }
+        Err(Error::ProgramKind {
+            name: program.name.clone(),
+            compute: program.is_compute(),
+        })
+    }
+
+    unsafe fn bind_program(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        handle: ProgramHandle,
+        color_format: vk::Format,
+    ) -> Result<()> {
+        let program = &mut self.programs[handle.0];
+        let compute = program.is_compute();
+        match &mut program.backend {
+            ShaderBackend::ShaderObjects { shaders } => {
+                let loader = self
+                    .extensions
+                    .shader_object
+                    .as_ref()
+                    .expect("Shader objects without a loader");
+                let mut stages = program.stages.clone();
+                let mut bound = shaders.clone();
+                // Every graphics stage the device enables has to be bound,
+                // the ones the program does not use to null.
+                if !compute {
+                    let mut graphics_stages = vec![vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT];
+                    if self.extensions.mesh_shader.is_some() {
+                        graphics_stages.extend([vk::ShaderStageFlags::TASK_EXT, vk::ShaderStageFlags::MESH_EXT]);
+                    }
+                    for stage in graphics_stages {
+                        if !stages.contains(&stage) {
+                            stages.push(stage);
+                            bound.push(vk::ShaderEXT::null());
+                        }
+                    }
+                }
+                loader.cmd_bind_shaders(command_buffer, &stages, &bound);
+            }
+            ShaderBackend::Pipelines { modules, pipelines } => {
+                let pipeline = match pipelines.get(&color_format) {
+                    Some(pipeline) => *pipeline,
+                    None => {
+                        let pipeline = if compute {
+                            Program::create_compute_pipeline(
+                                &self.device,
+                                modules[0],
+                                &program.entry_points[0],
+                                &program.specialization.info(),
+                                program.pipeline_layout,
+                            )?
+                        } else {
+                            Program::create_pipeline(
+                                &self.device,
+                                &program.stages,
+                                modules,
+                                &program.entry_points,
+                                &program.specialization.info(),
+                                program.pipeline_layout,
+                                color_format,
+                            )?
+                        };
+                        let name = if compute {
+                            program.name.clone()
+                        } else {
+                            format!("{} {color_format:?}", program.name)
+                        };
+                        self.debug.name(pipeline, &name);
+                        pipelines.insert(color_format, pipeline);
+                        pipeline
+                    }
+                };
+                let bind_point = if compute {
+                    vk::PipelineBindPoint::COMPUTE
+                } else {
+                    vk::PipelineBindPoint::GRAPHICS
+                };
+                self.device
+                    .cmd_bind_pipeline(command_buffer, bind_point, pipeline);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets all state the draw depends on. Pipelines only need the states in
+    /// [`DYNAMIC_STATES`], shader objects need every piece of state set.
+    ///
+    /// # Safety
+    ///
+    /// `command_buffer` has to be recording and come from the device of these
+    /// shaders.
+    pub unsafe fn set_dynamic_state(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        width: u32,
+        height: u32,
+    ) {
+        let device = &self.device;
+
+        device.cmd_set_viewport_with_count(
+            command_buffer,
+            &[vk::Viewport {
+                width: width as f32,
+                height: height as f32,
+                max_depth: 1.0,
+                ..Default::default()
+            }],
+        );
+        device.cmd_set_scissor_with_count(
+            command_buffer,
+            &[vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D { width, height },
+            }],
+        );
+        device.cmd_set_primitive_topology(command_buffer, vk::PrimitiveTopology::TRIANGLE_LIST);
+        device.cmd_set_primitive_restart_enable(command_buffer, false);
+        device.cmd_set_rasterizer_discard_enable(command_buffer, false);
+        device.cmd_set_cull_mode(command_buffer, vk::CullModeFlags::NONE);
+        device.cmd_set_front_face(command_buffer, vk::FrontFace::COUNTER_CLOCKWISE);
+        device.cmd_set_depth_test_enable(command_buffer, false);
+        device.cmd_set_depth_write_enable(command_buffer, false);
+        device.cmd_set_depth_compare_op(command_buffer, vk::CompareOp::ALWAYS);
+        device.cmd_set_depth_bounds_test_enable(command_buffer, false);
+        device.cmd_set_depth_bias_enable(command_buffer, false);
+        device.cmd_set_stencil_test_enable(command_buffer, false);
+        device.cmd_set_stencil_op(
+            command_buffer,
+            vk::StencilFaceFlags::FRONT_AND_BACK,
+            vk::StencilOp::KEEP,
+            vk::StencilOp::KEEP,
+            vk::StencilOp::KEEP,
+            vk::CompareOp::ALWAYS,
+        );
+
+        if let Some(loader) = &self.extensions.shader_object {
+            loader.cmd_set_vertex_input(command_buffer, &[], &[]);
+            loader.cmd_set_polygon_mode(command_buffer, vk::PolygonMode::FILL);
+            loader.cmd_set_rasterization_samples(command_buffer, vk::SampleCountFlags::TYPE_1);
+            loader.cmd_set_sample_mask(command_buffer, vk::SampleCountFlags::TYPE_1, &[!0]);
+            loader.cmd_set_alpha_to_coverage_enable(command_buffer, false);
+            loader.cmd_set_color_blend_enable(command_buffer, 0, &[vk::FALSE]);
+            loader.cmd_set_color_write_mask(command_buffer, 0, &[vk::ColorComponentFlags::RGBA]);
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        for program in &mut self.programs {
+            program.destroy(&self.device, &self.extensions);
         }
+        self.programs.clear();
+        self.handles.clear();
+        self.variants.clear();
     }
 }
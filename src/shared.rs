@@ -0,0 +1,13 @@
+//! Rust twins of the structs in `shaders/shared.hlsli`, generated by build.rs
+//! with the same layout. Padding is explicit, fill it with
+//! `..bytemuck::Zeroable::zeroed()`.
+//!
+//! Structs that encase lays out the same also derive `encase::ShaderType`.
+
+pub use generated::*;
+
+// Derived `ShaderType` impls contain assertion functions that are never called.
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/shared.rs"));
+}
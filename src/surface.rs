@@ -1,9 +1,17 @@
-use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use raw_window_handle::{
+    HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+};
+
+use crate::error::Result;
 
 #[derive(Clone)]
 pub struct Surface {
     pub inner: ash::vk::SurfaceKHR,
     pub loader: ash::extensions::khr::Surface,
+
+    // Kept to create the surface again after it was lost.
+    display_handle: RawDisplayHandle,
+    window_handle: RawWindowHandle,
 }
 
 pub struct SurfaceInfo {
@@ -17,7 +25,7 @@ impl Surface {
         entry: &ash::Entry,
         instance: &ash::Instance,
         window: &winit::window::Window,
-    ) -> Surface {
+    ) -> Result<Surface> {
         let inner = unsafe {
             ash_window::create_surface(
                 &entry,
@@ -25,38 +33,56 @@ impl Surface {
                 window.raw_display_handle(),
                 window.raw_window_handle(),
                 None,
-            )
-            .unwrap()
+            )?
         };
 
         let loader = ash::extensions::khr::Surface::new(&entry, &instance);
 
-        Surface {
+        Ok(Surface {
             inner,
             loader,
+
+            display_handle: window.raw_display_handle(),
+            window_handle: window.raw_window_handle(),
+        })
+    }
+
+    /// Destroys the surface and creates a new one for the same window. Any
+    /// swapchain of the old surface has to be destroyed first.
+    pub fn recreate(&mut self, entry: &ash::Entry, instance: &ash::Instance) -> Result<()> {
+        unsafe {
+            self.loader.destroy_surface(self.inner, None);
+            self.inner = ash::vk::SurfaceKHR::null();
+
+            self.inner = ash_window::create_surface(
+                entry,
+                instance,
+                self.display_handle,
+                self.window_handle,
+                None,
+            )?;
         }
+
+        Ok(())
     }
 
-    pub fn info(&self, physical_device: &ash::vk::PhysicalDevice) -> SurfaceInfo {
+    pub fn info(&self, physical_device: &ash::vk::PhysicalDevice) -> Result<SurfaceInfo> {
         let formats = unsafe {
             self.loader
-                .get_physical_device_surface_formats(*physical_device, self.inner)
-                .unwrap()
+                .get_physical_device_surface_formats(*physical_device, self.inner)?
         };
 
         let capabilities = unsafe {
             self.loader
-                .get_physical_device_surface_capabilities(*physical_device, self.inner)
-                .unwrap()
+                .get_physical_device_surface_capabilities(*physical_device, self.inner)?
         };
 
         let present_modes = unsafe {
             self.loader
-                .get_physical_device_surface_present_modes(*physical_device, self.inner)
-                .unwrap()
+                .get_physical_device_surface_present_modes(*physical_device, self.inner)?
         };
 
-        SurfaceInfo { capabilities, formats, present_modes }
+        Ok(SurfaceInfo { capabilities, formats, present_modes })
 
     }
 }
@@ -1,3 +1,5 @@
+use crate::error::Result;
+
 #[derive(Clone)]
 pub struct Swapchain {
     physical_device: ash::vk::PhysicalDevice,
@@ -7,6 +9,7 @@ pub struct Swapchain {
     pub inner: ash::vk::SwapchainKHR,
     pub loader: ash::extensions::khr::Swapchain,
 
+    pub format: ash::vk::Format,
     pub width: u32,
     pub height: u32,
 
@@ -22,10 +25,10 @@ impl Swapchain {
         surface: &crate::surface::Surface,
         width: u32,
         height: u32,
-    ) -> Swapchain {
+    ) -> Result<Swapchain> {
         let loader = ash::extensions::khr::Swapchain::new(instance, device);
 
-        let surface_info = surface.info(physical_device);
+        let surface_info = surface.info(physical_device)?;
         let format = surface_info.formats[0];
 
         let mut desired_image_count = surface_info.capabilities.min_image_count + 1;
@@ -72,14 +75,10 @@ impl Swapchain {
             .image_array_layers(1)
             .build();
 
-        let inner = unsafe {
-            loader
-                .create_swapchain(&swapchain_create_info, None)
-                .unwrap()
-        };
+        let inner = unsafe { loader.create_swapchain(&swapchain_create_info, None)? };
 
         let present_images: Vec<ash::vk::Image> =
-            unsafe { loader.get_swapchain_images(inner).unwrap() };
+            unsafe { loader.get_swapchain_images(inner)? };
         let present_image_views: Vec<ash::vk::ImageView> = present_images
             .iter()
             .map(|&image| {
@@ -101,11 +100,11 @@ impl Swapchain {
                     })
                     .image(image)
                     .build();
-                unsafe { device.create_image_view(&create_view_info, None).unwrap() }
+                unsafe { device.create_image_view(&create_view_info, None) }
             })
-            .collect();
+            .collect::<ash::prelude::VkResult<_>>()?;
 
-        Swapchain {
+        Ok(Swapchain {
             physical_device: physical_device.clone(),
             device: device.clone(),
             surface: surface.clone(),
@@ -113,32 +112,35 @@ impl Swapchain {
             inner,
             loader,
 
+            format: format.format,
             width,
             height,
 
             present_images,
             present_image_views,
-        }
+        })
     }
 
     pub fn destroy(&mut self) {
         unsafe {
             self.present_image_views
-                .iter()
-                .for_each(|v| self.device.destroy_image_view(*v, None));
+                .drain(..)
+                .for_each(|v| self.device.destroy_image_view(v, None));
             
             self.loader.destroy_swapchain(self.inner, None);
+            self.inner = ash::vk::SwapchainKHR::null();
         };
     }
     
-    pub fn recreate(&mut self, width: u32, height: u32) {
+    pub fn recreate(&mut self, width: u32, height: u32) -> Result<()> {
         self.width = width;
         self.height = height;
 
         self.destroy();
 
-        let surface_info = self.surface.info(&self.physical_device);
+        let surface_info = self.surface.info(&self.physical_device)?;
         let format = surface_info.formats[0];
+        self.format = format.format;
 
         let mut desired_image_count = surface_info.capabilities.min_image_count + 1;
         if surface_info.capabilities.max_image_count > 0
@@ -184,14 +186,10 @@ impl Swapchain {
             .image_array_layers(1)
             .build();
 
-        self.inner = unsafe {
-            self.loader
-                .create_swapchain(&swapchain_create_info, None)
-                .unwrap()
-        };
+        self.inner = unsafe { self.loader.create_swapchain(&swapchain_create_info, None)? };
 
         self.present_images =
-            unsafe { self.loader.get_swapchain_images(self.inner).unwrap() };
+            unsafe { self.loader.get_swapchain_images(self.inner)? };
         self.present_image_views = self.present_images
             .iter()
             .map(|&image| {
@@ -213,9 +211,11 @@ impl Swapchain {
                     })
                     .image(image)
                     .build();
-                unsafe { self.device.create_image_view(&create_view_info, None).unwrap() }
+                unsafe { self.device.create_image_view(&create_view_info, None) }
             })
-            .collect();
+            .collect::<ash::prelude::VkResult<_>>()?;
+
+        Ok(())
     }
 }
 
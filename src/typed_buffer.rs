@@ -0,0 +1,204 @@
+use std::{fmt, marker::PhantomData};
+
+use ash::vk;
+use encase::{
+    internal::{CreateFrom, WriteInto},
+    ShaderType, StorageBuffer, UniformBuffer,
+};
+use gpu_allocator::MemoryLocation;
+
+use crate::{
+    error::Result,
+    renderer::{BufferHandle, Renderer},
+};
+
+/// Memory layout the data of a [`TypedBuffer`] is written in.
+///
+/// Both follow the WGSL rules encase implements, where vectors are aligned to
+/// their size, `vec3` like `vec4`. DXC relaxes that by default for storage
+/// buffers and `vk::RawBufferLoad`: vectors only need the alignment of their
+/// components as long as they do not straddle 16 bytes. `b` of
+/// `struct { float a; float3 b; }` then sits at byte 4 in HLSL but at 16 here,
+/// so start vector members of shared structs at a multiple of their encase
+/// alignment, e.g. by putting `float3` members first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// std140. Panics on types that are not valid in a uniform buffer, e.g.
+    /// arrays with a stride that is not a multiple of 16.
+    Uniform,
+    /// std430, which also allows a runtime-sized array as the last member.
+    Storage,
+}
+
+impl Layout {
+    pub fn usage(self) -> vk::BufferUsageFlags {
+        match self {
+            Layout::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
+            Layout::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
+        }
+    }
+
+    /// Writes `value` to the start of `bytes`, failing if it does not fit.
+    pub fn write<T: ShaderType + WriteInto>(self, value: &T, bytes: &mut [u8]) -> Result<()> {
+        match self {
+            Layout::Uniform => UniformBuffer::new(bytes).write(value)?,
+            Layout::Storage => StorageBuffer::new(bytes).write(value)?,
+        }
+        Ok(())
+    }
+
+    /// Reads a value from the start of `bytes`. Runtime-sized arrays take up
+    /// the rest of `bytes`.
+    pub fn read<T: ShaderType + CreateFrom>(self, bytes: &[u8]) -> Result<T> {
+        Ok(match self {
+            Layout::Uniform => UniformBuffer::new(bytes).create()?,
+            Layout::Storage => StorageBuffer::new(bytes).create()?,
+        })
+    }
+
+    /// The bytes of `value`, exactly `value.size()` of them.
+    pub fn encode<T: ShaderType + WriteInto>(self, value: &T) -> Vec<u8> {
+        let mut bytes = vec![0; value.size().get() as usize];
+        self.write(value, &mut bytes).expect("The bytes fit the value");
+        bytes
+    }
+}
+
+/// Device address of a `T`, for push constants and buffers that shaders read
+/// with `vk::RawBufferLoad`.
+#[repr(transparent)]
+pub struct DeviceAddress<T> {
+    pub raw: vk::DeviceAddress,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> DeviceAddress<T> {
+    pub fn new(raw: vk::DeviceAddress) -> DeviceAddress<T> {
+        DeviceAddress {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// Derives would require T to implement the traits too.
+impl<T> Clone for DeviceAddress<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DeviceAddress<T> {}
+
+impl<T> PartialEq for DeviceAddress<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T> Eq for DeviceAddress<T> {}
+
+impl<T> fmt::Debug for DeviceAddress<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeviceAddress<{}>({:#x})", std::any::type_name::<T>(), self.raw)
+    }
+}
+
+impl<T> From<DeviceAddress<T>> for vk::DeviceAddress {
+    fn from(address: DeviceAddress<T>) -> Self {
+        address.raw
+    }
+}
+
+// A single u64.
+unsafe impl<T: 'static> bytemuck::Zeroable for DeviceAddress<T> {}
+unsafe impl<T: 'static> bytemuck::Pod for DeviceAddress<T> {}
+
+/// A host visible buffer holding a `T` in the layout shaders expect, written
+/// and read through encase instead of by copying the bytes of Rust structs.
+///
+/// `T` may end in a runtime-sized array, e.g. be a `Vec<Vec3>` or a struct
+/// with a `#[size(runtime)]` member. The buffer keeps its size, so writes
+/// with more elements than it was created for fail.
+///
+/// It is a persistent buffer of the renderer and survives device loss with
+/// the last value written to it, see [`Renderer::create_persistent_buffer`].
+pub struct TypedBuffer<T> {
+    pub buffer: BufferHandle,
+    pub layout: Layout,
+    pub size: vk::DeviceSize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: ShaderType + WriteInto> TypedBuffer<T> {
+    /// A buffer just large enough for `value`, holding it.
+    pub fn new(
+        renderer: &mut Renderer,
+        layout: Layout,
+        value: &T,
+        location: MemoryLocation,
+        name: &str,
+    ) -> Result<TypedBuffer<T>> {
+        let contents = layout.encode(value);
+        TypedBuffer::create(renderer, layout, &contents, location, name)
+    }
+
+    /// A zeroed buffer of `size` bytes, e.g. from `T::min_size()` or
+    /// `CalculateSizeFor::calculate_size_for` of a runtime-sized `T`.
+    /// `location` has to be host visible.
+    pub fn with_size(
+        renderer: &mut Renderer,
+        layout: Layout,
+        size: vk::DeviceSize,
+        location: MemoryLocation,
+        name: &str,
+    ) -> Result<TypedBuffer<T>> {
+        TypedBuffer::create(renderer, layout, &vec![0; size as usize], location, name)
+    }
+
+    fn create(
+        renderer: &mut Renderer,
+        layout: Layout,
+        contents: &[u8],
+        location: MemoryLocation,
+        name: &str,
+    ) -> Result<TypedBuffer<T>> {
+        Ok(TypedBuffer {
+            buffer: renderer.create_persistent_buffer(contents, layout.usage(), location, name)?,
+            layout,
+            size: contents.len() as u64,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn write(&self, renderer: &mut Renderer, value: &T) -> Result<()> {
+        let mut bytes = vec![0; self.size as usize];
+        self.layout.write(value, &mut bytes)?;
+        renderer.write_buffer(self.buffer, 0, &bytes);
+        Ok(())
+    }
+}
+
+impl<T: ShaderType + CreateFrom> TypedBuffer<T> {
+    /// Reads back the value, e.g. after a shader wrote it. Runtime-sized
+    /// arrays get as many elements as fit the buffer.
+    pub fn read(&self, renderer: &Renderer) -> Result<T> {
+        let bytes = renderer
+            .persistent_buffer(self.buffer)
+            .allocation
+            .mapped_slice()
+            .expect("Host visible memory is mapped");
+        self.layout.read(&bytes[..self.size as usize])
+    }
+}
+
+impl<T> TypedBuffer<T> {
+    /// Changes when the device is lost and the buffer recreated.
+    pub fn address(&self, renderer: &Renderer) -> DeviceAddress<T> {
+        DeviceAddress::new(renderer.persistent_buffer(self.buffer).address)
+    }
+
+    pub fn destroy(&self, renderer: &mut Renderer) {
+        renderer.destroy_persistent_buffer(self.buffer);
+    }
+}
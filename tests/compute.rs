@@ -0,0 +1,69 @@
+use encase::CalculateSizeFor;
+use gpu_allocator::MemoryLocation;
+use vulkan_modern::{
+    app::{App, AppConfig},
+    renderer::{Dispatch, DispatchSize},
+    shaders::{PermutationKey, ProgramHandle},
+    shared::FillPushConstants,
+    typed_buffer::{Layout, TypedBuffer},
+};
+
+const COUNT: u32 = 100;
+
+/// Runs `program` from fill.hlsl over `COUNT` values and reads them back.
+fn fill(app: &mut App, program: ProgramHandle) -> Vec<u32> {
+    let output = TypedBuffer::<Vec<u32>>::with_size(
+        &mut app.renderer,
+        Layout::Storage,
+        Vec::<u32>::calculate_size_for(COUNT as u64).get(),
+        MemoryLocation::GpuToCpu,
+        "Fill output",
+    )
+    .unwrap();
+
+    let push_constants = FillPushConstants {
+        output_address: output.address(&app.renderer).raw,
+        count: COUNT,
+        padding: 0,
+    };
+
+    app.renderer.queue_dispatch(Dispatch {
+        program,
+        push_constants: bytemuck::bytes_of(&push_constants).to_vec(),
+        size: DispatchSize::Groups([COUNT.div_ceil(64), 1, 1]),
+    });
+    app.render().unwrap();
+    app.wait_gpu_idle().unwrap();
+
+    let values = output.read(&app.renderer).unwrap();
+    output.destroy(&mut app.renderer);
+    values
+}
+
+// Needs a Vulkan device; CI runs it under lavapipe.
+#[test]
+fn dispatch_fills_buffer() {
+    let mut config = AppConfig::from_env_and_args(std::iter::empty()).unwrap();
+    config.debug.collect = true;
+    config.validation.synchronization = true;
+    let mut app = App::new_headless(64, 64, &config).unwrap();
+
+    let fill_program = app.renderer.shaders().require("fill").unwrap();
+    assert_eq!(fill(&mut app, fill_program), (0..COUNT).map(|i| i * 2).collect::<Vec<_>>());
+
+    // Needs DXC or a populated shader cache for the variant.
+    let key = PermutationKey::new().flag("OFFSET").constant("SCALE", 3u32);
+    let variant = app.renderer.permutation(fill_program, &key).unwrap();
+    assert_ne!(variant, fill_program);
+    assert_eq!(app.renderer.permutation(fill_program, &key).unwrap(), variant);
+    assert_eq!(
+        app.renderer.permutation(fill_program, &PermutationKey::new().constant("SCALE", 2u32)).unwrap(),
+        fill_program
+    );
+    assert_eq!(fill(&mut app, variant), (0..COUNT).map(|i| i * 3 + 1).collect::<Vec<_>>());
+
+    assert!(app.renderer.permutation(fill_program, &PermutationKey::new().flag("MISSING")).is_err());
+    assert!(app.renderer.permutation(fill_program, &PermutationKey::new().constant("SCALE", 1.5f32)).is_err());
+
+    app.check_validation().unwrap();
+}
@@ -0,0 +1,18 @@
+use vulkan_modern::app::ValidationConfig;
+
+#[test]
+fn validation_options() {
+    let config = ValidationConfig::parse("sync, best-practices").unwrap();
+    assert!(config.enabled);
+    assert!(config.synchronization);
+    assert!(config.best_practices);
+    assert!(!config.gpu_assisted);
+    assert!(!config.required);
+
+    assert!(!ValidationConfig::parse("off").unwrap().enabled);
+    assert_eq!(ValidationConfig::parse("").unwrap(), ValidationConfig::default());
+    assert!(ValidationConfig::parse("sync,everything").is_err());
+    assert!(ValidationConfig::parse("off,gpu").unwrap().enabled);
+    assert!(!ValidationConfig::parse("required,off").unwrap().enabled);
+    assert!(ValidationConfig::parse("gpu,sync").is_err());
+}
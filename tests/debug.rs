@@ -0,0 +1,86 @@
+use ash::vk::{DebugUtilsMessageSeverityFlagsEXT as Severity, DebugUtilsMessageTypeFlagsEXT as Type};
+use vulkan_modern::{
+    debug::{DebugConfig, DebugCounters, DebugMessage, DebugSink},
+    error::Error,
+};
+
+fn message(severity: Severity, message_type: Type, id_name: &str, id_number: i32, text: &str) -> DebugMessage {
+    DebugMessage {
+        severity,
+        message_type,
+        id_name: id_name.to_owned(),
+        id_number,
+        message: text.to_owned(),
+        objects: vec!["BUFFER 0x1 \"Readback\"".to_owned()],
+    }
+}
+
+#[test]
+fn sink_counts_and_deduplicates() {
+    let sink = DebugSink::new(DebugConfig::default()).unwrap();
+
+    assert!(sink.handle(message(Severity::ERROR, Type::VALIDATION, "VUID-a", 1, "first")));
+    assert!(!sink.handle(message(Severity::ERROR, Type::VALIDATION, "VUID-a", 1, "again")));
+    assert!(sink.handle(message(Severity::WARNING, Type::VALIDATION, "VUID-b", 2, "other")));
+    // Messages without an ID are always logged.
+    assert!(sink.handle(message(Severity::INFO, Type::GENERAL, "", 0, "loader")));
+    assert!(sink.handle(message(Severity::INFO, Type::GENERAL, "", 0, "loader")));
+
+    assert_eq!(
+        sink.counters(),
+        DebugCounters {
+            errors: 2,
+            warnings: 1,
+            infos: 2,
+            verbose: 0,
+        }
+    );
+    assert_eq!(sink.suppressed(), vec![("VUID-a".to_owned(), 1, 1)]);
+}
+
+#[test]
+fn sink_creates_file() {
+    let path = std::env::temp_dir().join(format!("vulkan-modern-debug-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let sink = DebugSink::new(DebugConfig {
+        file: Some(path.clone()),
+        deduplicate: false,
+        ..DebugConfig::default()
+    })
+    .unwrap();
+    sink.handle(message(Severity::ERROR, Type::VALIDATION, "VUID-a", 1, "first"));
+    sink.handle(message(Severity::ERROR, Type::VALIDATION, "VUID-a", 1, "again"));
+    drop(sink);
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(contents.contains("first"));
+    assert!(contents.contains("again"));
+}
+
+#[test]
+fn sink_collects_warnings_and_errors() {
+    let sink = DebugSink::new(DebugConfig {
+        collect: true,
+        ..DebugConfig::default()
+    })
+    .unwrap();
+    assert!(sink.check().is_ok());
+
+    sink.handle(message(Severity::ERROR, Type::VALIDATION, "VUID-a", 1, "first"));
+    sink.handle(message(Severity::ERROR, Type::VALIDATION, "VUID-a", 1, "again"));
+    sink.handle(message(Severity::INFO, Type::GENERAL, "", 0, "loader"));
+
+    match sink.check() {
+        Err(Error::Validation(messages)) => {
+            assert_eq!(messages.len(), 2);
+            let report = Error::Validation(messages).to_string();
+            assert!(report.contains("VUID-a"));
+            assert!(report.contains("Readback"));
+        }
+        result => panic!("expected validation messages, got {result:?}"),
+    }
+    // Checking drains the collector.
+    assert!(sink.check().is_ok());
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::{collections::BTreeMap, path::Path};
+
+use ash::vk;
+use vulkan_modern::{
+    front_end::{compile_stage, SourceLanguage},
+    manifest::{Manifest, Stage, StageDesc},
+    reflection::Reflection,
+    shader_cache::ShaderCache,
+};
+
+use common::TempDir;
+
+const GLSL: &str = r#"
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+} pushConstants;
+
+layout(set = 0, binding = 0) uniform Globals {
+    vec4 offset;
+} globals;
+
+void main() {
+    gl_Position = pushConstants.model * globals.offset;
+}
+"#;
+
+const WGSL: &str = r#"
+struct PushConstants {
+    model: mat4x4<f32>,
+}
+
+struct Globals {
+    offset: vec4<f32>,
+}
+
+var<push_constant> pushConstants: PushConstants;
+@group(0) @binding(0) var<uniform> globals: Globals;
+
+@vertex
+fn vertexMain() -> @builtin(position) vec4<f32> {
+    return pushConstants.model * globals.offset;
+}
+"#;
+
+fn vertex_stage(source: &str, entry_point: &str) -> StageDesc {
+    StageDesc {
+        stage: Stage::Vertex,
+        source: source.into(),
+        entry_point: entry_point.to_owned(),
+        profile: String::new(),
+        defines: BTreeMap::new(),
+        args: Vec::new(),
+    }
+}
+
+#[test]
+fn language_from_extension() {
+    let language = |path: &str| SourceLanguage::from_path(Path::new(path));
+    assert_eq!(language("a.hlsl"), Some(SourceLanguage::Hlsl));
+    assert_eq!(language("a.frag"), Some(SourceLanguage::Glsl));
+    assert_eq!(language("a.comp"), Some(SourceLanguage::Glsl));
+    assert_eq!(language("a.wgsl"), Some(SourceLanguage::Wgsl));
+    assert_eq!(language("a.spv"), Some(SourceLanguage::Spirv));
+    assert_eq!(language("a.metal"), None);
+    assert_eq!(language("hlsl"), None);
+}
+
+#[test]
+fn glsl_and_wgsl_reflect_alike() {
+    let directory = TempDir::new("front-ends");
+    std::fs::write(directory.join("shader.vert"), GLSL).unwrap();
+    std::fs::write(directory.join("shader.wgsl"), WGSL).unwrap();
+    let cache = ShaderCache::new(directory.join("cache"));
+
+    let glsl = compile_stage(&directory, &[], &vertex_stage("shader.vert", "main"), &cache).unwrap().spirv;
+    let wgsl = compile_stage(&directory, &[], &vertex_stage("shader.wgsl", "vertexMain"), &cache).unwrap().spirv;
+    let glsl = Reflection::new(&glsl).unwrap();
+    let wgsl = Reflection::new(&wgsl).unwrap();
+
+    assert_eq!(glsl.entry_points[0].name, "main");
+    assert_eq!(wgsl.entry_points[0].name, "vertexMain");
+    for reflection in [&glsl, &wgsl] {
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.push_constant_size, 64);
+        assert_eq!(reflection.bindings.len(), 1);
+        assert_eq!(reflection.bindings[0].binding, 0);
+        assert_eq!(reflection.bindings[0].descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+    }
+}
+
+#[test]
+fn spirv_is_loaded_as_is() {
+    let directory = TempDir::new("front-end-spirv");
+    std::fs::write(directory.join("shader.wgsl"), WGSL).unwrap();
+    let cache = ShaderCache::new(directory.join("cache"));
+
+    let spirv = compile_stage(&directory, &[], &vertex_stage("shader.wgsl", "vertexMain"), &cache).unwrap().spirv;
+    std::fs::write(directory.join("shader.spv"), &spirv).unwrap();
+    let loaded = compile_stage(&directory, &[], &vertex_stage("shader.spv", "vertexMain"), &cache).unwrap().spirv;
+    assert_eq!(loaded, spirv);
+}
+
+#[test]
+fn reports_naga_errors() {
+    let directory = TempDir::new("front-end-errors");
+    std::fs::write(directory.join("broken.wgsl"), "fn vertexMain( {").unwrap();
+    let cache = ShaderCache::new(directory.join("cache"));
+
+    assert!(compile_stage(&directory, &[], &vertex_stage("broken.wgsl", "vertexMain"), &cache).is_err());
+    assert!(compile_stage(&directory, &[], &vertex_stage("missing.wgsl", "vertexMain"), &cache).is_err());
+}
+
+#[test]
+fn manifest_checks_sources() {
+    let program = |source: &str, stage: &str, extra: &str| {
+        format!(
+            "[[program]]\nname = \"p\"\n\n[[program.stage]]\nstage = \"{stage}\"\nsource = \"{source}\"\nentry_point = \"main\"\n{extra}\n"
+        )
+    };
+
+    assert!(Manifest::parse(&program("a.vert", "vertex", "")).is_ok());
+    assert!(Manifest::parse(&program("a.wgsl", "compute", "")).is_ok());
+    assert!(Manifest::parse(&program("a.spv", "mesh", "")).is_ok());
+    assert!(Manifest::parse(&program("a.frag", "fragment", "defines = { RED = \"\" }")).is_ok());
+
+    let error = |source, stage, extra| Manifest::parse(&program(source, stage, extra)).unwrap_err();
+    assert!(error("a.hlsl", "vertex", "").contains("profile"));
+    assert!(error("a.wgsl", "vertex", "profile = \"vs_6_6\"").contains("profile"));
+    assert!(error("a.wgsl", "vertex", "defines = { RED = \"\" }").contains("defines"));
+    assert!(error("a.glsl", "mesh", "").contains("Mesh"));
+    assert!(error("a.metal", "vertex", "").contains("language"));
+}
@@ -0,0 +1,46 @@
+use vulkan_modern::golden::{compare, GoldenTest, Image};
+
+// Needs a Vulkan device; CI runs it under lavapipe. The reference is what this
+// test renders there: run it with GOLDEN_UPDATE=1 on lavapipe to create or
+// replace tests/golden/triangle.png, review the image and check it in.
+#[test]
+fn triangle() {
+    GoldenTest::new("triangle").run().unwrap();
+}
+
+#[test]
+fn compare_respects_tolerance() {
+    let expected = Image {
+        width: 2,
+        height: 1,
+        pixels: vec![100, 100, 100, 255, 0, 0, 0, 255],
+    };
+    let actual = Image {
+        width: 2,
+        height: 1,
+        pixels: vec![102, 100, 100, 255, 0, 0, 10, 255],
+    };
+
+    let comparison = compare(&actual, &expected, 2);
+    assert_eq!(comparison.mismatched_pixels, 1);
+    assert_eq!(comparison.max_difference, 10);
+    assert_eq!(&comparison.diff.pixels[4..8], &[255, 0, 0, 255]);
+
+    assert!(compare(&actual, &expected, 10).passed());
+}
+
+#[test]
+fn compare_rejects_different_sizes() {
+    let expected = Image {
+        width: 1,
+        height: 1,
+        pixels: vec![0; 4],
+    };
+    let actual = Image {
+        width: 2,
+        height: 1,
+        pixels: vec![0; 8],
+    };
+
+    assert!(!compare(&actual, &expected, 255).passed());
+}
@@ -0,0 +1,212 @@
+use std::path::Path;
+
+use vulkan_modern::{
+    manifest::{ConstantValue, Manifest, Stage, MANIFEST_FILE},
+    shaders::{PermutationKey, SHADER_DIR},
+};
+
+const TWO_PROGRAMS: &str = r#"
+[[program]]
+name = "triangle"
+
+[[program.stage]]
+stage = "vertex"
+source = "triangle.hlsl"
+entry_point = "vertexMain"
+profile = "vs_6_6"
+
+[[program.stage]]
+stage = "fragment"
+source = "triangle.hlsl"
+entry_point = "pixelMain"
+profile = "ps_6_6"
+defines = { RED = "", SCALE = "2" }
+
+[[program]]
+name = "unlit"
+
+[[program.stage]]
+stage = "vertex"
+source = "unlit.hlsl"
+entry_point = "main"
+profile = "vs_6_6"
+"#;
+
+#[test]
+fn parses_programs() {
+    let manifest = Manifest::parse(TWO_PROGRAMS).unwrap();
+    assert_eq!(manifest.programs.len(), 2);
+
+    let triangle = manifest.program("triangle").unwrap();
+    assert_eq!(triangle.stages.len(), 2);
+    assert_eq!(triangle.stages[0].stage, Stage::Vertex);
+    assert_eq!(triangle.stages[1].defines["SCALE"], "2");
+    assert!(triangle.stages[0].defines.is_empty());
+    assert!(manifest.program("missing").is_none());
+    assert!(manifest.include_paths.is_empty());
+
+    let with_includes = Manifest::parse(&format!("include_paths = [\"common\"]\n{TWO_PROGRAMS}")).unwrap();
+    assert_eq!(with_includes.include_paths, [Path::new("common")]);
+}
+
+#[test]
+fn shipped_manifest_is_valid() {
+    let manifest = Manifest::load(&Path::new(SHADER_DIR).join(MANIFEST_FILE)).unwrap();
+    assert!(manifest.program("triangle").is_some());
+    assert_eq!(manifest.program("fill").unwrap().stages[0].stage, Stage::Compute);
+
+    let mesh = manifest.program("triangle_mesh").unwrap();
+    assert!(mesh.has_mesh_stages());
+    assert_eq!(mesh.fallback.as_deref(), Some("triangle"));
+}
+
+#[test]
+fn rejects_invalid_programs() {
+    let duplicate = TWO_PROGRAMS.replace("\"unlit\"", "\"triangle\"");
+    assert!(Manifest::parse(&duplicate).unwrap_err().contains("twice"));
+
+    let out_of_order = r#"
+        [[program]]
+        name = "backwards"
+
+        [[program.stage]]
+        stage = "fragment"
+        source = "a.hlsl"
+        entry_point = "ps"
+        profile = "ps_6_6"
+
+        [[program.stage]]
+        stage = "vertex"
+        source = "a.hlsl"
+        entry_point = "vs"
+        profile = "vs_6_6"
+    "#;
+    assert!(Manifest::parse(out_of_order).unwrap_err().contains("pipeline order"));
+
+    let mixed = TWO_PROGRAMS.replace("\"fragment\"", "\"compute\"");
+    assert!(Manifest::parse(&mixed).unwrap_err().contains("compute"));
+
+    assert!(Manifest::parse("[[program]]\nname = \"empty\"\nstage = []\n").is_err());
+    assert!(Manifest::parse(&TWO_PROGRAMS.replace("\"vertex\"", "\"hull\"")).is_err());
+}
+
+const MESH_PROGRAM: &str = r#"
+[[program]]
+name = "mesh"
+fallback = "triangle"
+
+[[program.stage]]
+stage = "mesh"
+source = "mesh.hlsl"
+entry_point = "meshMain"
+profile = "ms_6_6"
+
+[[program.stage]]
+stage = "fragment"
+source = "triangle.hlsl"
+entry_point = "pixelMain"
+profile = "ps_6_6"
+"#;
+
+#[test]
+fn validates_mesh_programs() {
+    let manifest = Manifest::parse(&format!("{TWO_PROGRAMS}{MESH_PROGRAM}")).unwrap();
+    assert!(manifest.program("mesh").unwrap().has_mesh_stages());
+    assert!(!manifest.program("triangle").unwrap().has_mesh_stages());
+
+    let missing_fallback = MESH_PROGRAM.replace("\"triangle\"", "\"missing\"");
+    assert!(Manifest::parse(&missing_fallback).unwrap_err().contains("does not exist"));
+
+    let mesh_fallback = format!("{MESH_PROGRAM}{}", MESH_PROGRAM.replace("name = \"mesh\"", "name = \"triangle\""));
+    assert!(Manifest::parse(&mesh_fallback).unwrap_err().contains("mesh stages itself"));
+
+    let fallback_without_mesh = TWO_PROGRAMS.replace("name = \"unlit\"", "name = \"unlit\"\nfallback = \"triangle\"");
+    assert!(Manifest::parse(&fallback_without_mesh).unwrap_err().contains("no mesh stages"));
+
+    let vertex_and_mesh = format!("{TWO_PROGRAMS}{}", MESH_PROGRAM.replace("\"fragment\"", "\"vertex\""));
+    assert!(Manifest::parse(&vertex_and_mesh).unwrap_err().contains("vertex stage with mesh"));
+
+    let task_only = MESH_PROGRAM.replace("stage = \"mesh\"", "stage = \"task\"");
+    assert!(Manifest::parse(&format!("{TWO_PROGRAMS}{task_only}")).unwrap_err().contains("no mesh stage"));
+}
+
+const PERMUTED: &str = r#"
+[[program]]
+name = "lit"
+
+[[program.stage]]
+stage = "fragment"
+source = "lit.hlsl"
+entry_point = "main"
+profile = "ps_6_6"
+
+[[program.axis]]
+define = "TEXTURED"
+
+[[program.axis]]
+define = "SKINNING"
+values = ["STATIC", "LINEAR"]
+
+[[program.constant]]
+name = "SAMPLES"
+id = 0
+default = 4
+
+[[program.constant]]
+name = "EXPOSURE"
+id = 1
+default = 1.0
+"#;
+
+#[test]
+fn resolves_permutations() {
+    let manifest = Manifest::parse(PERMUTED).unwrap();
+    let lit = manifest.program("lit").unwrap();
+
+    let defaults = PermutationKey::new().resolve(lit).unwrap();
+    assert_eq!(defaults.defines.len(), 1);
+    assert_eq!(defaults.defines["SKINNING"], "STATIC");
+    assert_eq!(defaults.constants["SAMPLES"], ConstantValue::Int(4));
+    let explicit_defaults = PermutationKey::new()
+        .define("SKINNING", "STATIC")
+        .constant("SAMPLES", 4u32);
+    assert_eq!(explicit_defaults.resolve(lit).unwrap(), defaults);
+
+    let variant = PermutationKey::new()
+        .flag("TEXTURED")
+        .define("SKINNING", "LINEAR")
+        .constant("EXPOSURE", 2.0f32)
+        .resolve(lit)
+        .unwrap();
+    assert_eq!(variant.defines["TEXTURED"], "1");
+    assert_eq!(variant.constants["EXPOSURE"].bits(), 2.0f32.to_bits());
+    assert_ne!(variant, defaults);
+
+    assert!(PermutationKey::new().flag("SHADOWS").resolve(lit).is_err());
+    assert!(PermutationKey::new().define("SKINNING", "DUAL").resolve(lit).is_err());
+    assert!(PermutationKey::new().define("TEXTURED", "0").resolve(lit).is_err());
+    assert!(PermutationKey::new().constant("SAMPLES", 4.0f32).resolve(lit).is_err());
+    assert!(PermutationKey::new().constant("GAMMA", 2.2f32).resolve(lit).is_err());
+
+    let all = PermutationKey::all(lit);
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[0].resolve(lit).unwrap(), defaults);
+    assert!(all.contains(&PermutationKey::new().flag("TEXTURED").define("SKINNING", "LINEAR")));
+    assert!(all.iter().all(|key| key.resolve(lit).is_ok()));
+}
+
+#[test]
+fn rejects_invalid_permutations() {
+    let error = |from: &str, to: &str| {
+        let text = PERMUTED.replace(from, to);
+        assert_ne!(text, PERMUTED);
+        Manifest::parse(&text).unwrap_err()
+    };
+
+    assert!(error("\"SKINNING\"\nvalues", "\"TEXTURED\"\nvalues").contains("twice"));
+    assert!(error("[\"STATIC\", \"LINEAR\"]", "[\"STATIC\", \"STATIC\"]").contains("repeated"));
+    assert!(error("profile = \"ps_6_6\"", "profile = \"ps_6_6\"\ndefines = { TEXTURED = \"\" }").contains("also a define"));
+    assert!(error("source = \"lit.hlsl\"\nentry_point = \"main\"\nprofile = \"ps_6_6\"", "source = \"lit.wgsl\"\nentry_point = \"main\"").contains("needs defines"));
+    assert!(error("id = 1", "id = 0").contains("reuses"));
+    assert!(error("default = 4", "default = 5000000000").contains("32 bits"));
+}
@@ -0,0 +1,221 @@
+use ash::vk;
+use vulkan_modern::{
+    manifest::Stage,
+    reflection::{self, Binding, EntryPoint, Member, Reflection, ShaderLayout},
+    shader_cache::{CompileRequest, ShaderCache},
+    shaders::ShaderCode,
+    shared::{FillPushConstants, Globals, TrianglePushConstants},
+};
+
+fn stage(name: &str, stage: vk::ShaderStageFlags, push_constant_size: u32, bindings: Vec<Binding>) -> Reflection {
+    Reflection {
+        entry_points: vec![EntryPoint {
+            name: name.to_owned(),
+            stage,
+        }],
+        push_constant_size,
+        push_constant_members: Vec::new(),
+        bindings,
+    }
+}
+
+fn binding(binding: u32, descriptor_type: vk::DescriptorType) -> Binding {
+    Binding {
+        set: 0,
+        binding,
+        descriptor_type,
+        count: 1,
+        name: None,
+    }
+}
+
+// Needs DXC at runtime.
+#[test]
+fn triangle_push_constants() {
+    let code = ShaderCode::compile().unwrap();
+    let triangle = code.program("triangle").unwrap();
+    let vertex = Reflection::new(triangle.stage(Stage::Vertex).unwrap()).unwrap();
+    let fragment = Reflection::new(triangle.stage(Stage::Fragment).unwrap()).unwrap();
+
+    assert_eq!(vertex.entry_points[0].name, "vertexMain");
+    assert_eq!(vertex.push_constant_size, 8);
+    assert_eq!(fragment.stages(), vk::ShaderStageFlags::FRAGMENT);
+
+    let layout = ShaderLayout::merge(&[vertex, fragment]).unwrap();
+    assert_eq!(layout.push_constant_ranges.len(), 1);
+    assert_eq!(layout.push_constant_ranges[0].size, 8);
+    assert_eq!(layout.push_constant_stages(), vk::ShaderStageFlags::VERTEX);
+}
+
+// Needs DXC at runtime.
+#[test]
+fn shared_structs_match_reflection() {
+    let cache = ShaderCache::from_env();
+    let reflect = |name: &str| {
+        let source = format!(
+            "#include \"shared.hlsli\"\n\
+             [[vk::push_constant]] {name} pushConstants;\n\
+             [numthreads(1, 1, 1)] void main() {{ vk::RawBufferStore<{name}>(0, pushConstants); }}\n"
+        );
+        let output = cache
+            .compile(&CompileRequest {
+                directory: "shaders",
+                path: "shaders/shared_test.hlsl",
+                source: &source,
+                entry_point: "main",
+                profile: "cs_6_6",
+                args: &["-spirv"],
+                defines: &[],
+            })
+            .unwrap();
+        Reflection::new(&output.spirv).unwrap()
+    };
+    // Every member has to sit where DXC puts it, not just the total size match.
+    let check = |name: &str, size: usize, members: &[(&str, u32)]| {
+        let reflection = reflect(name);
+        assert_eq!(reflection.push_constant_size as usize, size, "size of {name}");
+        let reflected: Vec<_> = reflection
+            .push_constant_members
+            .iter()
+            .map(|member| (member.name.as_deref().unwrap_or_default(), member.offset))
+            .collect();
+        assert_eq!(reflected, members, "members of {name}");
+    };
+
+    check("Globals", std::mem::size_of::<Globals>(), Globals::MEMBERS);
+    check(
+        "TrianglePushConstants",
+        std::mem::size_of::<TrianglePushConstants>(),
+        TrianglePushConstants::MEMBERS,
+    );
+    check("FillPushConstants", std::mem::size_of::<FillPushConstants>(), FillPushConstants::MEMBERS);
+}
+
+#[test]
+fn merge_combines_stages() {
+    let layout = ShaderLayout::merge(&[
+        stage("vs", vk::ShaderStageFlags::VERTEX, 16, vec![binding(0, vk::DescriptorType::UNIFORM_BUFFER)]),
+        stage("ps", vk::ShaderStageFlags::FRAGMENT, 16, vec![
+            binding(1, vk::DescriptorType::SAMPLED_IMAGE),
+            binding(0, vk::DescriptorType::UNIFORM_BUFFER),
+        ]),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        layout.push_constant_stages(),
+        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+    );
+    let set = &layout.sets[&0];
+    assert_eq!(set.len(), 2);
+    assert_eq!(set[0].stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+    assert_eq!(set[1].stage_flags, vk::ShaderStageFlags::FRAGMENT);
+}
+
+#[test]
+fn merge_rejects_mismatches() {
+    assert!(ShaderLayout::merge(&[
+        stage("vs", vk::ShaderStageFlags::VERTEX, 8, vec![]),
+        stage("ps", vk::ShaderStageFlags::FRAGMENT, 16, vec![]),
+    ])
+    .is_err());
+
+    assert!(ShaderLayout::merge(&[
+        stage("vs", vk::ShaderStageFlags::VERTEX, 0, vec![binding(0, vk::DescriptorType::UNIFORM_BUFFER)]),
+        stage("ps", vk::ShaderStageFlags::FRAGMENT, 0, vec![binding(0, vk::DescriptorType::STORAGE_BUFFER)]),
+    ])
+    .is_err());
+}
+
+#[test]
+fn reflects_mesh_shaders() {
+    // A mesh entry point with a push constant block, plus capabilities,
+    // instructions and storage classes of SPV_EXT_mesh_shader.
+    let words: [u32; 46] = [
+        0x0723_0203, 0x0001_0600, 0, 8, 0,
+        (2 << 16) | 17, 5283, // OpCapability MeshShadingEXT
+        (5 << 16) | 15, 5365, 1, u32::from_le_bytes(*b"main"), 0, // OpEntryPoint MeshEXT %1 "main"
+        (3 << 16) | 71, 3, 2, // OpDecorate %3 Block
+        (5 << 16) | 72, 3, 0, 35, 0, // OpMemberDecorate %3 0 Offset 0
+        (4 << 16) | 21, 2, 32, 0, // %2 = OpTypeInt 32 0
+        (3 << 16) | 30, 3, 2, // %3 = OpTypeStruct %2
+        (4 << 16) | 32, 4, 9, 3, // %4 = OpTypePointer PushConstant %3
+        (4 << 16) | 59, 4, 5, 9, // %5 = OpVariable %4 PushConstant
+        (4 << 16) | 32, 6, 5402, 2, // %6 = OpTypePointer TaskPayloadWorkgroupEXT %2
+        (4 << 16) | 59, 6, 7, 5402, // %7 = OpVariable %6 TaskPayloadWorkgroupEXT
+        (3 << 16) | 5295, 2, 2, // OpSetMeshOutputsEXT %2 %2
+    ];
+    let spirv: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    let reflection = Reflection::new(&spirv).unwrap();
+    assert_eq!(reflection.entry_points[0].name, "main");
+    assert_eq!(reflection.stages(), vk::ShaderStageFlags::MESH_EXT);
+    assert_eq!(reflection.push_constant_size, 4);
+    assert_eq!(reflection.push_constant_members, [Member { name: None, offset: 0 }]);
+    assert!(reflection.bindings.is_empty());
+}
+
+#[test]
+fn reflects_member_names() {
+    // A compute entry point with a push constant block of two named members.
+    let words: [u32; 48] = [
+        0x0723_0203, 0x0001_0500, 0, 6, 0,
+        (5 << 16) | 15, 5, 1, u32::from_le_bytes(*b"main"), 0, // OpEntryPoint GLCompute %1 "main"
+        (5 << 16) | 6, 3, 0, u32::from_le_bytes(*b"scal"), u32::from_le_bytes(*b"e\0\0\0"), // OpMemberName %3 0 "scale"
+        (5 << 16) | 6, 3, 1, u32::from_le_bytes(*b"bias"), 0, // OpMemberName %3 1 "bias"
+        (3 << 16) | 71, 3, 2, // OpDecorate %3 Block
+        (5 << 16) | 72, 3, 0, 35, 0, // OpMemberDecorate %3 0 Offset 0
+        (5 << 16) | 72, 3, 1, 35, 4, // OpMemberDecorate %3 1 Offset 4
+        (3 << 16) | 22, 2, 32, // %2 = OpTypeFloat 32
+        (4 << 16) | 30, 3, 2, 2, // %3 = OpTypeStruct %2 %2
+        (4 << 16) | 32, 4, 9, 3, // %4 = OpTypePointer PushConstant %3
+        (4 << 16) | 59, 4, 5, 9, // %5 = OpVariable %4 PushConstant
+    ];
+    let spirv: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    let reflection = Reflection::new(&spirv).unwrap();
+    assert_eq!(reflection.push_constant_size, 8);
+    assert_eq!(
+        reflection.push_constant_members,
+        [
+            Member { name: Some("scale".to_owned()), offset: 0 },
+            Member { name: Some("bias".to_owned()), offset: 4 },
+        ]
+    );
+}
+
+#[test]
+fn validates_structure() {
+    // An empty compute entry point.
+    let words: Vec<u32> = vec![
+        0x0723_0203, 0x0001_0500, 0, 5, 0,
+        (2 << 16) | 17, 1, // OpCapability Shader
+        (3 << 16) | 14, 0, 1, // OpMemoryModel Logical GLSL450
+        (5 << 16) | 15, 5, 3, u32::from_le_bytes(*b"main"), 0, // OpEntryPoint GLCompute %3 "main"
+        (6 << 16) | 16, 3, 17, 1, 1, 1, // OpExecutionMode %3 LocalSize 1 1 1
+        (2 << 16) | 19, 1, // %1 = OpTypeVoid
+        (3 << 16) | 33, 2, 1, // %2 = OpTypeFunction %1
+        (5 << 16) | 54, 1, 3, 0, 2, // %3 = OpFunction %1 None %2
+        (2 << 16) | 248, 4, // %4 = OpLabel
+        (1 << 16) | 253, // OpReturn
+        (1 << 16) | 56, // OpFunctionEnd
+    ];
+    let validate = |words: &[u32]| {
+        let spirv: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        reflection::validate(&spirv)
+    };
+    let patched = |index: usize, word: u32| {
+        let mut words = words.clone();
+        words[index] = word;
+        validate(&words)
+    };
+
+    assert!(validate(&words).is_ok());
+    assert!(validate(&words[..words.len() - 1]).is_err(), "missing OpFunctionEnd");
+    assert!(validate(&words[..words.len() - 2]).is_err(), "truncated OpReturn");
+    assert!(patched(1, 0x0002_0000).is_err(), "version 2.0");
+    assert!(patched(3, 4).is_err(), "id above the bound");
+    assert!(patched(32, 2).is_err(), "id defined twice");
+    assert!(patched(12, 2).is_err(), "entry point naming a type");
+    assert!(patched(7, (1 << 16) | 253).is_err(), "no OpMemoryModel");
+}
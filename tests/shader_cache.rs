@@ -0,0 +1,126 @@
+mod common;
+
+use vulkan_modern::shader_cache::{CompileRequest, ShaderBinaryCache, ShaderCache};
+
+use common::TempDir;
+
+fn cache(directory: &TempDir, compiler_version: Option<&str>) -> ShaderCache {
+    ShaderCache {
+        directory: directory.to_path_buf(),
+        compiler_version: compiler_version.map(str::to_owned),
+    }
+}
+
+const REQUEST: CompileRequest = CompileRequest {
+    directory: "",
+    path: "test.hlsl",
+    source: "float4 main() : SV_TARGET { return 1; }",
+    entry_point: "main",
+    profile: "ps_6_6",
+    args: &["-spirv"],
+    defines: &[],
+};
+
+#[test]
+fn key_covers_defines() {
+    let defined = CompileRequest {
+        defines: &[("FAST", None)],
+        ..REQUEST
+    };
+    assert_ne!(REQUEST.key(), defined.key());
+    assert_eq!(REQUEST.key(), REQUEST.key());
+}
+
+#[test]
+fn key_is_relative_to_directory() {
+    let relative = CompileRequest {
+        directory: "shaders",
+        path: "shaders/test.hlsl",
+        args: &["-spirv", "-I", "shaders/include"],
+        ..REQUEST
+    };
+    let absolute = CompileRequest {
+        directory: "/project/shaders",
+        path: "/project/shaders/test.hlsl",
+        args: &["-spirv", "-I", "/project/shaders/include"],
+        ..REQUEST
+    };
+    assert_eq!(relative.key(), absolute.key());
+    assert_ne!(relative.key(), CompileRequest { path: "shaders/other.hlsl", ..relative }.key());
+}
+
+#[test]
+fn hit_requires_same_compiler_and_includes() {
+    let directory = TempDir::new("cache-hit");
+    let cache = cache(&directory, Some("1"));
+    let include = directory.join("common.hlsli");
+    std::fs::write(&include, "// v1").unwrap();
+
+    cache.store(&REQUEST, &[1, 2, 3, 4], std::slice::from_ref(&include)).unwrap();
+    assert_eq!(cache.load(&REQUEST), Some(vec![1, 2, 3, 4]));
+
+    let other_compiler = ShaderCache {
+        directory: cache.directory.clone(),
+        compiler_version: Some("2".to_owned()),
+    };
+    assert_eq!(other_compiler.load(&REQUEST), None);
+
+    // Without a compiler any entry with matching inputs is used.
+    let no_compiler = ShaderCache {
+        directory: cache.directory.clone(),
+        compiler_version: None,
+    };
+    let output = no_compiler.compile(&REQUEST).unwrap();
+    assert_eq!(output.spirv, vec![1, 2, 3, 4]);
+    assert_eq!(output.includes, vec![include.clone()]);
+
+    std::fs::write(&include, "// v2").unwrap();
+    assert_eq!(cache.load(&REQUEST), None);
+}
+
+#[test]
+fn miss_without_compiler_fails() {
+    let directory = TempDir::new("cache-miss");
+    let cache = cache(&directory, None);
+    assert!(cache.compile(&REQUEST).is_err());
+}
+
+// Needs DXC at runtime.
+#[test]
+fn records_includes() {
+    let directory = TempDir::new("includes");
+    let cache = ShaderCache::new(directory.to_path_buf());
+    let headers = directory.join("headers");
+    std::fs::create_dir_all(&headers).unwrap();
+    std::fs::write(directory.join("color.hlsli"), "#include \"value.hlsli\"\n#define COLOR VALUE").unwrap();
+    std::fs::write(headers.join("value.hlsli"), "#define VALUE 1").unwrap();
+
+    let path = directory.join("main.hlsl");
+    let headers = headers.to_string_lossy();
+    let request = CompileRequest {
+        path: &path.to_string_lossy(),
+        source: "#include \"color.hlsli\"\nfloat4 main() : SV_TARGET { return COLOR; }",
+        args: &["-spirv", "-I", &headers],
+        ..REQUEST
+    };
+
+    let output = cache.compile(&request).unwrap();
+    let names: Vec<_> = output.includes.iter().map(|include| include.file_name().unwrap()).collect();
+    assert_eq!(names, ["color.hlsli", "value.hlsli"]);
+    assert_eq!(cache.load_output(&request).unwrap().includes, output.includes);
+}
+
+#[test]
+fn binaries_load_only_complete_sets() {
+    let directory = TempDir::new("binaries");
+    let cache = ShaderBinaryCache {
+        directory: directory.to_path_buf(),
+    };
+    let key = ShaderBinaryCache::key(&[&[1, 2, 3, 4], &[5, 6, 7, 8]]);
+    assert_ne!(key, ShaderBinaryCache::key(&[&[1, 2, 3, 4, 5, 6, 7, 8]]));
+
+    assert_eq!(cache.load(&key, 2), None);
+    cache.store(&key, &[vec![1], vec![2]]).unwrap();
+    assert_eq!(cache.load(&key, 2), Some(vec![vec![1], vec![2]]));
+    assert_eq!(cache.load(&key, 3), None);
+}
@@ -0,0 +1,92 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use vulkan_modern::{
+    app::{App, AppConfig},
+    error::Error,
+    shader_test::{ShaderFunction, ENTRY_POINT},
+};
+
+/// Wraps `trianglePosition` of transform.hlsli, whose arguments have to come
+/// from a single input.
+const TRANSFORM: &str = r#"
+#include "transform.hlsli"
+
+struct TransformInput
+{
+    float4x4 model;
+    uint vertexId;
+    uint padding0;
+    uint padding1;
+    uint padding2;
+};
+
+float4 transform(TransformInput input)
+{
+    return trianglePosition(input.vertexId, input.model);
+}
+"#;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TransformInput {
+    model: Mat4,
+    vertex_id: u32,
+    padding: [u32; 3],
+}
+
+// Plain data without implicit padding.
+unsafe impl Zeroable for TransformInput {}
+unsafe impl Pod for TransformInput {}
+
+const TRIANGLE_POSITIONS: [Vec2; 3] = [Vec2::new(0.0, -0.5), Vec2::new(0.5, 0.5), Vec2::new(-0.5, 0.5)];
+
+fn transform() -> ShaderFunction {
+    ShaderFunction::new(TRANSFORM, "transform", "TransformInput", "float4")
+}
+
+#[test]
+fn wrapper_calls_function() {
+    let wrapper = transform().wrapper();
+    assert!(wrapper.starts_with(TRANSFORM));
+    assert!(wrapper.contains(&format!("void {ENTRY_POINT}(")));
+    assert!(wrapper.contains("vk::RawBufferLoad<TransformInput>"));
+    assert!(wrapper.contains("float4 output = transform(input);"));
+    assert!(wrapper.contains("vk::RawBufferStore<float4>"));
+}
+
+// Needs a Vulkan device and DXC; CI runs it under lavapipe.
+#[test]
+fn triangle_positions_are_transformed() {
+    let mut config = AppConfig::from_env_and_args(std::iter::empty()).unwrap();
+    config.debug.collect = true;
+    let mut app = App::new_headless(64, 64, &config).unwrap();
+
+    // Translations and rotations catch a transposed matrix or mul() order.
+    // More inputs than a workgroup has invocations.
+    let models = [
+        Mat4::IDENTITY,
+        Mat4::from_scale(Vec3::new(2.0, 3.0, 4.0)),
+        Mat4::from_translation(Vec3::new(0.25, -0.5, 0.75)),
+        Mat4::from_rotation_z(1.0) * Mat4::from_translation(Vec3::new(1.0, 2.0, 0.0)),
+    ];
+    let inputs: Vec<TransformInput> = (0..100u32)
+        .map(|i| TransformInput {
+            model: models[i as usize % models.len()],
+            vertex_id: i % 3,
+            padding: [0; 3],
+        })
+        .collect();
+
+    let outputs: Vec<Vec4> = transform().run(&mut app, &inputs).unwrap();
+    assert_eq!(outputs.len(), inputs.len());
+    for (input, output) in inputs.iter().zip(&outputs) {
+        let position = TRIANGLE_POSITIONS[input.vertex_id as usize];
+        let expected = input.model * position.extend(0.0).extend(1.0);
+        assert!(output.abs_diff_eq(expected, 1e-6), "{output} != {expected}");
+    }
+
+    assert!(transform().run::<TransformInput, Vec4>(&mut app, &[]).unwrap().is_empty());
+    assert!(matches!(transform().run::<(), Vec4>(&mut app, &[(); 4]), Err(Error::ZeroSized { .. })));
+    assert!(matches!(transform().run::<TransformInput, ()>(&mut app, &inputs), Err(Error::ZeroSized { .. })));
+    app.check_validation().unwrap();
+}
@@ -0,0 +1,119 @@
+use ash::vk;
+use encase::{ArrayLength, ShaderType};
+use glam::{Mat4, Vec3};
+use gpu_allocator::MemoryLocation;
+use vulkan_modern::{
+    app::{App, AppConfig},
+    error::Error,
+    shared::Globals,
+    typed_buffer::{DeviceAddress, Layout, TypedBuffer},
+};
+
+use types::{Light, Lights};
+
+// encase's derive adds `check` functions that only exist for their trait
+// bounds and are never called.
+#[allow(dead_code)]
+mod types {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+    pub struct Light {
+        pub color: Vec3,
+        pub intensity: f32,
+        pub direction: Vec3,
+    }
+
+    #[derive(Debug, PartialEq, ShaderType)]
+    pub struct Lights {
+        pub count: ArrayLength,
+        #[size(runtime)]
+        pub lights: Vec<Light>,
+    }
+}
+
+fn light(i: u32) -> Light {
+    let i = i as f32;
+    Light {
+        color: Vec3::new(i, i + 0.25, i + 0.5),
+        intensity: i * 2.0,
+        direction: Vec3::Z * i,
+    }
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4).map(bytemuck::pod_read_unaligned).collect()
+}
+
+#[test]
+fn vec3_members_are_aligned() {
+    let bytes = Layout::Storage.encode(&light(1));
+    assert_eq!(bytes.len(), 32);
+    // The float packs into the vec3's fourth component, the next vec3 does not.
+    assert_eq!(floats(&bytes), [1.0, 1.25, 1.5, 2.0, 0.0, 0.0, 1.0, 0.0]);
+    assert_eq!(Layout::Uniform.encode(&light(1)), bytes);
+    assert_eq!(Layout::Storage.read::<Light>(&bytes).unwrap(), light(1));
+}
+
+#[test]
+fn runtime_sized_arrays() {
+    let lights = Lights {
+        count: ArrayLength,
+        lights: (0..3).map(light).collect(),
+    };
+    let bytes = Layout::Storage.encode(&lights);
+    assert_eq!(bytes.len(), 16 + 3 * 32);
+    assert_eq!(u32::from_le_bytes(bytes[..4].try_into().unwrap()), 3);
+    assert_eq!(floats(&bytes[16 + 2 * 32..][..16]), [2.0, 2.25, 2.5, 4.0]);
+    assert_eq!(Layout::Storage.read::<Lights>(&bytes).unwrap(), lights);
+
+    let mut small = vec![0; 16 + 2 * 32];
+    assert!(matches!(
+        Layout::Storage.write(&lights, &mut small),
+        Err(Error::BufferTooSmall { expected: 112, found: 80 })
+    ));
+}
+
+#[test]
+#[should_panic]
+fn uniform_rejects_tight_arrays() {
+    Layout::Uniform.encode(&[1.0f32; 4]);
+}
+
+#[test]
+fn shared_structs_encode_like_bytes() {
+    let globals = Globals {
+        transform: Mat4::from_rotation_z(1.0),
+    };
+    assert_eq!(Layout::Uniform.encode(&globals), bytemuck::bytes_of(&globals));
+
+    let address = DeviceAddress::<Globals>::new(0x1000);
+    assert_eq!(bytemuck::bytes_of(&address), 0x1000u64.to_le_bytes());
+}
+
+// Needs a Vulkan device; CI runs it under lavapipe.
+#[test]
+fn buffers_survive_device_recovery() {
+    let config = AppConfig::from_env_and_args(std::iter::empty()).unwrap();
+    let mut app = App::new_headless(64, 64, &config).unwrap();
+
+    let lights = Lights {
+        count: ArrayLength,
+        lights: (0..3).map(light).collect(),
+    };
+    let typed =
+        TypedBuffer::new(&mut app.renderer, Layout::Storage, &lights, MemoryLocation::CpuToGpu, "Lights").unwrap();
+    let mut plain = app
+        .renderer
+        .create_buffer(16, vk::BufferUsageFlags::STORAGE_BUFFER, MemoryLocation::CpuToGpu, "Plain")
+        .unwrap();
+
+    app.recover_device().unwrap();
+
+    assert_eq!(typed.read(&app.renderer).unwrap(), lights);
+    assert_ne!(typed.address(&app.renderer).raw, 0);
+    assert!(!app.renderer.owns(&plain));
+    app.renderer.destroy_buffer(&mut plain);
+
+    typed.destroy(&mut app.renderer);
+}